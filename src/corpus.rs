@@ -0,0 +1,312 @@
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, read_dir};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A directory tree of corpus files, filtered by include and exclude glob patterns.
+///
+/// Patterns containing a `/` are matched against the path relative to the corpus root,
+/// anything else is matched against the file or directory name alone. `*` and `?` never
+/// match a `/`, `**` matches any number of directories and `[a-z]` / `[!a-z]` match
+/// character classes. With no include patterns only `*.txt` files are read.
+pub struct Corpus {
+    root: PathBuf,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+/// The files found by walking a `Corpus`, along with everything that was passed over.
+pub struct CorpusFiles {
+    pub files: Vec<PathBuf>,
+    pub skipped: Vec<Skipped>,
+}
+
+pub struct Skipped {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+#[derive(Debug)]
+pub enum SkipReason {
+    Excluded(String),
+    NotIncluded,
+    NotAFile,
+    Io(io::Error),
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            SkipReason::Excluded(ref p) => write!(f, "excluded by pattern '{}'", p),
+            SkipReason::NotIncluded => write!(f, "did not match any include pattern"),
+            SkipReason::NotAFile => write!(f, "not a regular file or directory"),
+            SkipReason::Io(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl Corpus {
+    pub fn new<P: AsRef<Path>>(root: P) -> Corpus {
+        Corpus {
+            root: root.as_ref().to_path_buf(),
+            include: vec![],
+            exclude: vec![],
+        }
+    }
+
+    pub fn include(mut self, pattern: &str) -> Corpus {
+        self.include.push(Pattern::new(pattern));
+        self
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Corpus {
+        self.exclude.push(Pattern::new(pattern));
+        self
+    }
+
+    /// Recursively lists the corpus files in a stable, sorted order.
+    pub fn walk(&self) -> CorpusFiles {
+        let mut found = CorpusFiles {
+            files: vec![],
+            skipped: vec![],
+        };
+        self.walk_dir(&self.root, &mut found);
+        found
+    }
+
+    fn walk_dir(&self, dir: &Path, found: &mut CorpusFiles) {
+        let entries = read_dir(dir).and_then(|entries| entries.collect::<io::Result<Vec<_>>>());
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(e) => return found.skip(dir, SkipReason::Io(e)),
+        };
+
+        let mut paths: Vec<_> = entries.into_iter().map(|e| e.path()).collect();
+        paths.sort();
+
+        for path in paths {
+            let relative = self.relative(&path);
+            if let Some(pattern) = self.exclude.iter().find(|p| p.matches_path(&relative)) {
+                found.skip(&path, SkipReason::Excluded(pattern.source.clone()));
+                continue;
+            }
+
+            // symlinks to files are followed, but we never descend into a linked
+            // directory so that a cycle can't trap the walk
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(m) => m,
+                Err(e) => {
+                    found.skip(&path, SkipReason::Io(e));
+                    continue;
+                }
+            };
+
+            if metadata.is_dir() {
+                self.walk_dir(&path, found);
+                continue;
+            }
+
+            match fs::metadata(&path) {
+                Ok(ref m) if m.is_file() => {}
+                Ok(_) => {
+                    found.skip(&path, SkipReason::NotAFile);
+                    continue;
+                }
+                Err(e) => {
+                    found.skip(&path, SkipReason::Io(e));
+                    continue;
+                }
+            }
+
+            if self.is_included(&relative) {
+                found.files.push(path);
+            } else {
+                found.skip(&path, SkipReason::NotIncluded);
+            }
+        }
+    }
+
+    fn is_included(&self, relative: &str) -> bool {
+        if self.include.is_empty() {
+            relative.ends_with(".txt")
+        } else {
+            self.include.iter().any(|p| p.matches_path(relative))
+        }
+    }
+
+    fn relative(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        relative.components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join("/")
+    }
+}
+
+impl CorpusFiles {
+    fn skip(&mut self, path: &Path, reason: SkipReason) {
+        self.skipped.push(Skipped {
+            path: path.to_path_buf(),
+            reason: reason,
+        });
+    }
+}
+
+struct Pattern {
+    source: String,
+    tokens: Vec<GlobToken>,
+    anchored: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum GlobToken {
+    Char(char),
+    AnyChar,
+    AnyRun,
+    AnyDirs,
+    Class(Vec<(char, char)>, bool),
+}
+
+impl Pattern {
+    fn new(source: &str) -> Pattern {
+        let trimmed = source.trim_start_matches("./");
+        Pattern {
+            source: source.to_string(),
+            tokens: parse_glob(trimmed),
+            anchored: trimmed.contains('/'),
+        }
+    }
+
+    fn matches_path(&self, relative: &str) -> bool {
+        if self.anchored {
+            self.matches(relative)
+        } else {
+            self.matches(relative.rsplit('/').next().unwrap_or(relative))
+        }
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        glob_match(&self.tokens, &text)
+    }
+}
+
+fn parse_glob(pattern: &str) -> Vec<GlobToken> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                // `**/` swallows its slash so that it can also match no directories at all
+                i += 2;
+                if chars.get(i) == Some(&'/') {
+                    i += 1;
+                }
+                tokens.push(GlobToken::AnyDirs);
+                continue;
+            }
+            '*' => tokens.push(GlobToken::AnyRun),
+            '?' => tokens.push(GlobToken::AnyChar),
+            '[' => {
+                if let Some((class, next)) = parse_class(&chars, i + 1) {
+                    tokens.push(class);
+                    i = next;
+                    continue;
+                }
+                tokens.push(GlobToken::Char('['));
+            }
+            c => tokens.push(GlobToken::Char(c)),
+        }
+        i += 1;
+    }
+    tokens
+}
+
+fn parse_class(chars: &[char], mut i: usize) -> Option<(GlobToken, usize)> {
+    let negated = chars.get(i) == Some(&'!');
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = vec![];
+    let start = i;
+    while i < chars.len() {
+        if chars[i] == ']' && i > start {
+            return Some((GlobToken::Class(ranges, negated), i + 1));
+        }
+        if chars.get(i + 1) == Some(&'-') && i + 2 < chars.len() && chars[i + 2] != ']' {
+            ranges.push((chars[i], chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((chars[i], chars[i]));
+            i += 1;
+        }
+    }
+    None
+}
+
+fn glob_match(tokens: &[GlobToken], text: &[char]) -> bool {
+    let (token, rest) = match tokens.split_first() {
+        Some(t) => t,
+        None => return text.is_empty(),
+    };
+
+    match *token {
+        GlobToken::AnyDirs => (0..text.len() + 1).any(|i| glob_match(rest, &text[i..])),
+        GlobToken::AnyRun => {
+            for i in 0..text.len() + 1 {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if i < text.len() && text[i] == '/' {
+                    break;
+                }
+            }
+            false
+        }
+        GlobToken::AnyChar => {
+            !text.is_empty() && text[0] != '/' && glob_match(rest, &text[1..])
+        }
+        GlobToken::Char(c) => !text.is_empty() && text[0] == c && glob_match(rest, &text[1..]),
+        GlobToken::Class(ref ranges, negated) => {
+            if text.is_empty() || text[0] == '/' {
+                return false;
+            }
+            let c = text[0];
+            let in_class = ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+            in_class != negated && glob_match(rest, &text[1..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Pattern;
+
+    #[test]
+    fn test_name_patterns() {
+        let txt = Pattern::new("*.txt");
+        assert!(txt.matches_path("a.txt"));
+        assert!(txt.matches_path("deep/dir/b.txt"));
+        assert!(!txt.matches_path("a.txt.gz"));
+
+        let class = Pattern::new("part-[0-9]?.[!x]ml");
+        assert!(class.matches_path("part-12.yml"));
+        assert!(!class.matches_path("part-a2.yml"));
+        assert!(!class.matches_path("part-12.xml"));
+    }
+
+    #[test]
+    fn test_path_patterns() {
+        let star = Pattern::new("news/*.txt");
+        assert!(star.matches_path("news/a.txt"));
+        assert!(!star.matches_path("news/2015/a.txt"));
+        assert!(!star.matches_path("other/news/a.txt"));
+
+        let dirs = Pattern::new("**/drafts/**");
+        assert!(dirs.matches_path("drafts/a.txt"));
+        assert!(dirs.matches_path("books/drafts/x/y.md"));
+        assert!(!dirs.matches_path("books/drafted/y.md"));
+    }
+}
@@ -6,6 +6,8 @@ use std::num;
 #[derive(Debug)]
 pub enum Error {
     FormatError,
+    Mismatch(String),
+    Config(String),
     Other(Box<error::Error>),
 }
 
@@ -13,6 +15,8 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::FormatError => write!(f, "Invalid format for vocabulary file"),
+            Error::Mismatch(ref s) => write!(f, "Models don't match: {}", s),
+            Error::Config(ref s) => write!(f, "Invalid training configuration: {}", s),
             Error::Other(ref e) => e.fmt(f),
         }
     }
@@ -22,13 +26,15 @@ impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::FormatError => "Invalid format for vocabulary file",
+            Error::Mismatch(_) => "Models don't match",
+            Error::Config(_) => "Invalid training configuration",
             Error::Other(ref e) => e.description(),
         }
     }
 
     fn cause(&self) -> Option<&error::Error> {
         match *self {
-            Error::FormatError => None,
+            Error::FormatError | Error::Mismatch(_) | Error::Config(_) => None,
             Error::Other(ref e) => e.cause(),
         }
     }
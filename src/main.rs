@@ -1,20 +1,28 @@
 extern crate clap;
 extern crate time;
 extern crate rand;
+extern crate yaml_rust;
 
 mod models;
 mod parser;
 mod mayberef;
 mod processing;
 mod error;
+mod corpus;
 
 
-use clap::{Arg, App};
+use clap::{Arg, App, ArgMatches};
 use std::io::{BufRead, Read, stdin};
 use std::fs;
-use std::path::Path;
-use models::LanguageModelBuilder;
-use processing::{find_most_common_words, save_words, load_most_common_words, create_model};
+use std::env;
+use std::time::Duration;
+use std::path::{Path, PathBuf};
+use models::{LanguageModel, LanguageModelBuilder, Boundary, ContextWeighting, Vectors,
+             TrainingConfig, Init, Progress, Architecture, TRAINERS};
+use corpus::Corpus;
+use processing::{find_most_common_words, save_words, load_most_common_words, create_model,
+                 train_word2vec};
+use processing::{Segmenter, Segmentation, Tokenizer};
 
 fn main() {
     let matches = App::new("gauntlet")
@@ -26,11 +34,240 @@ fn main() {
                                .long("corpus")
                                .help("Sets a directory to search for the corpus")
                                .takes_value(true))
+                      .arg(Arg::with_name("INCLUDE")
+                               .short("i")
+                               .long("include")
+                               .help("Only reads corpus files matching this glob pattern, \
+                                      may be given more than once; defaults to *.txt")
+                               .takes_value(true)
+                               .multiple(true))
+                      .arg(Arg::with_name("EXCLUDE")
+                               .short("x")
+                               .long("exclude")
+                               .help("Skips corpus files and directories matching this glob \
+                                      pattern, may be given more than once")
+                               .takes_value(true)
+                               .multiple(true))
+                      .arg(Arg::with_name("TOKENIZER")
+                               .short("t")
+                               .long("tokenizer")
+                               .help("How to split the corpus into words: 'ascii' (the default) \
+                                      keeps only runs of ASCII letters, 'unicode' keeps \
+                                      accented and non-Latin letters too")
+                               .takes_value(true))
+                      .arg(Arg::with_name("SEGMENT")
+                               .short("S")
+                               .long("segment")
+                               .help("How to find the sentences that context windows stay \
+                                      within: 'sentence' (the default) or 'paragraph', which \
+                                      only breaks at blank lines")
+                               .takes_value(true))
+                      .arg(Arg::with_name("WINDOW_SCOPE")
+                               .long("window_scope")
+                               .help("The boundary that context windows may not cross: \
+                                      'sentence' (the default), 'paragraph' or 'document'")
+                               .takes_value(true))
+                      .arg(Arg::with_name("WINDOW_RADIUS")
+                               .short("r")
+                               .long("window_radius")
+                               .help("How many words either side of a word count as its \
+                                      context, defaults to 10")
+                               .takes_value(true))
+                      .arg(Arg::with_name("LEFT_RADIUS")
+                               .long("left_radius")
+                               .help("How many words before a word count as its context, \
+                                      overriding --window_radius; 0 gives right-only windows")
+                               .takes_value(true))
+                      .arg(Arg::with_name("RIGHT_RADIUS")
+                               .long("right_radius")
+                               .help("How many words after a word count as its context, \
+                                      overriding --window_radius; 0 gives left-only windows")
+                               .takes_value(true))
+                      .arg(Arg::with_name("POSITIONAL")
+                               .long("positional")
+                               .help("Counts a context word separately for each offset it is \
+                                      seen at, giving more syntactic vectors"))
+                      .arg(Arg::with_name("WEIGHTING")
+                               .long("weighting")
+                               .help("How much a context word counts for by its distance: \
+                                      'harmonic' (1/distance, the default), 'uniform', \
+                                      'linear' or 'dynamic' for word2vec style random windows")
+                               .takes_value(true))
+                      .arg(Arg::with_name("SEED")
+                               .long("seed")
+                               .help("Seeds the random window sizes and the starting vectors \
+                                      for training, defaults to 0; saved with the model, so it \
+                                      can't be changed for a loaded one")
+                               .takes_value(true))
+                      .arg(Arg::with_name("FIRST_DOCUMENT")
+                               .long("first_document")
+                               .help("Numbers the corpus files from this rather than 0, so that \
+                                      parts of a corpus counted separately for --merge draw \
+                                      different dynamic windows")
+                               .takes_value(true))
+                      .arg(Arg::with_name("MEMORY_BUDGET")
+                               .long("memory_budget")
+                               .help("Roughly how many megabytes of co-occurrence counts to \
+                                      hold in memory before spilling them to disk; each \
+                                      reading thread may hold as much again")
+                               .takes_value(true))
+                      .arg(Arg::with_name("SPILL_DIR")
+                               .long("spill_dir")
+                               .help("Where to write spilled co-occurrence counts, defaults to \
+                                      the system temporary directory")
+                               .takes_value(true))
+                      .arg(Arg::with_name("THREADS")
+                               .short("T")
+                               .long("threads")
+                               .help("The number of threads to read the corpus with, \
+                                      defaults to 1")
+                               .takes_value(true))
+                      .arg(Arg::with_name("CONFIG")
+                               .long("config")
+                               .help("Reads training settings from a YAML file, with any of \
+                                      trainer, rank, epochs, min_improvement, time_budget, \
+                                      learning_rate, x_max, alpha, init, init_scale, \
+                                      regularisation, smoothing, eigenvalue_weight, negative, \
+                                      subsample, subwords, min_n, max_n, buckets, vectors, \
+                                      threads, checkpoint, checkpoint_every and resume; the \
+                                      flags below override it")
+                               .takes_value(true))
+                      .arg(Arg::with_name("TRAINER")
+                               .long("trainer")
+                               .help("How to train the vectors: 'glove' (the default), 'als' \
+                                      to solve GloVe's loss by alternating least squares, \
+                                      'svd' of the counts' positive PMI, or word2vec's \
+                                      'skipgram' or 'cbow' straight from the corpus")
+                               .takes_value(true))
+                      .arg(Arg::with_name("RANK")
+                               .long("rank")
+                               .help("The number of dimensions of the trained vectors, \
+                                      defaults to 200")
+                               .takes_value(true))
+                      .arg(Arg::with_name("EPOCHS")
+                               .short("e")
+                               .long("epochs")
+                               .help("The most passes to make over the training data, defaults \
+                                      to 5 for skipgram and cbow and 100 otherwise")
+                               .takes_value(true))
+                      .arg(Arg::with_name("MIN_IMPROVEMENT")
+                               .long("min_improvement")
+                               .help("Stops training once an epoch lowers the loss by less \
+                                      than this fraction, e.g. 0.001")
+                               .takes_value(true))
+                      .arg(Arg::with_name("TIME_BUDGET")
+                               .long("time_budget")
+                               .help("Stops training after the first epoch to end past this \
+                                      many seconds")
+                               .takes_value(true))
+                      .arg(Arg::with_name("LEARNING_RATE")
+                               .long("learning_rate")
+                               .help("The initial learning rate, defaults to 0.025 for \
+                                      skipgram and 0.05 otherwise")
+                               .takes_value(true))
+                      .arg(Arg::with_name("X_MAX")
+                               .long("x_max")
+                               .help("Co-occurrence counts above this get full weight, \
+                                      defaults to 100")
+                               .takes_value(true))
+                      .arg(Arg::with_name("ALPHA")
+                               .long("alpha")
+                               .help("The exponent of the weighting of counts below x_max, \
+                                      defaults to 0.75")
+                               .takes_value(true))
+                      .arg(Arg::with_name("INIT")
+                               .long("init")
+                               .help("How to draw the starting vectors: 'uniform' (the \
+                                      default), 'gaussian' or 'xavier'")
+                               .takes_value(true))
+                      .arg(Arg::with_name("INIT_SCALE")
+                               .long("init_scale")
+                               .help("Starting vectors are spread init_scale / rank from \
+                                      zero, defaults to 0.5")
+                               .takes_value(true))
+                      .arg(Arg::with_name("REGULARISATION")
+                               .long("regularisation")
+                               .help("For ALS, the penalty on the vectors' squared length, \
+                                      defaults to 0.01")
+                               .takes_value(true))
+                      .arg(Arg::with_name("SMOOTHING")
+                               .long("smoothing")
+                               .help("For SVD, the power context counts are raised to in the \
+                                      PMI, defaults to 0.75")
+                               .takes_value(true))
+                      .arg(Arg::with_name("EIGENVALUE_WEIGHT")
+                               .long("eigenvalue_weight")
+                               .help("For SVD, the power of the singular values the vectors \
+                                      are scaled by, defaults to 0.5")
+                               .takes_value(true))
+                      .arg(Arg::with_name("NEGATIVE")
+                               .long("negative")
+                               .help("For word2vec, how many negative samples to draw for \
+                                      each word predicted, defaults to 5")
+                               .takes_value(true))
+                      .arg(Arg::with_name("SUBSAMPLE")
+                               .long("subsample")
+                               .help("For word2vec, randomly drops words more common than \
+                                      this fraction of the corpus, defaults to 0.001")
+                               .takes_value(true))
+                      .arg(Arg::with_name("SUBWORDS")
+                               .long("subwords")
+                               .help("For word2vec, learns vectors for character n-grams too, \
+                                      so that words outside the vocabulary can be queried; \
+                                      needs the word vectors alone"))
+                      .arg(Arg::with_name("MIN_N")
+                               .long("min_n")
+                               .help("The shortest character n-grams to learn, defaults to 3")
+                               .takes_value(true))
+                      .arg(Arg::with_name("MAX_N")
+                               .long("max_n")
+                               .help("The longest character n-grams to learn, defaults to 6")
+                               .takes_value(true))
+                      .arg(Arg::with_name("BUCKETS")
+                               .long("buckets")
+                               .help("How many vectors character n-grams are hashed into, \
+                                      defaults to 100000")
+                               .takes_value(true))
+                      .arg(Arg::with_name("TRAINING_THREADS")
+                               .long("training_threads")
+                               .help("The number of threads to train with, defaults to 1; \
+                                      training with more isn't exactly reproducible")
+                               .takes_value(true))
+                      .arg(Arg::with_name("CHECKPOINT")
+                               .long("checkpoint")
+                               .help("Saves where training has got to in this file every few \
+                                      epochs; only the glove trainer can")
+                               .takes_value(true))
+                      .arg(Arg::with_name("CHECKPOINT_EVERY")
+                               .long("checkpoint_every")
+                               .help("How many epochs to train between checkpoints, defaults \
+                                      to 10")
+                               .takes_value(true))
+                      .arg(Arg::with_name("RESUME")
+                               .long("resume")
+                               .help("Carries on training from the checkpoint file, if it \
+                                      exists, giving the same model as if training had never \
+                                      stopped"))
+                      .arg(Arg::with_name("VECTORS")
+                               .short("v")
+                               .long("vectors")
+                               .help("Which trained vectors to query: 'words', 'contexts' or \
+                                      'sum' of the two, defaults to 'words' for skipgram and \
+                                      cbow and 'sum' otherwise")
+                               .takes_value(true))
                       .arg(Arg::with_name("LOAD")
                                .short("l")
                                .long("load")
-                               .help("Loads a pre-saved language model")
+                               .help("Loads a pre-saved language model; with --corpus, \
+                                      counts that corpus into it as well")
                                .takes_value(true))
+                      .arg(Arg::with_name("MERGE")
+                               .short("m")
+                               .long("merge")
+                               .help("Adds together the counts of several saved models built \
+                                      from different parts of a corpus")
+                               .takes_value(true)
+                               .multiple(true))
                       .arg(Arg::with_name("SAVE")
                                .short("s")
                                .long("save")
@@ -52,51 +289,45 @@ fn main() {
                                .help("The maximum number of words to use in the vocabulary \
                                       list, defaults to 30000")
                                .takes_value(true))
+                      .arg(Arg::with_name("NUM_CONTEXTS")
+                               .long("num_contexts")
+                               .help("How many of the most common words to count as contexts, \
+                                      defaults to the same as --num_words")
+                               .takes_value(true))
                       .get_matches();
 
-    let (load, corpus) = (matches.value_of("LOAD"), matches.value_of("CORPUS"));
-    let builder = match (load, corpus) {
-        (Some(l), None) => LanguageModelBuilder::load(Path::new(&l)).expect("Couldn't load model"),
-        (None, Some(corpus)) => {
-            let corpus = Path::new(corpus);
-            let num_words = matches.value_of("NUM_WORDS")
-                                   .map(|n| n.parse().expect("Number of words was invalid"))
-                                   .unwrap_or(30000);
-
-            let words = match matches.value_of("LOAD_WORDS") {
-                None => find_most_common_words(corpus, num_words),
-                Some(file) => {
-                    fs::File::open(file)
-                        .map_err(|e| From::from(e))
-                        .and_then(|f| load_most_common_words(f, num_words))
-                        .unwrap_or_else(|e| {
-                            panic!("Error loading most vocabulary list: {}", e);
-                        })
-                }
-            };
-
-            if let Some(wordfile) = matches.value_of("WORDS") {
-                if let Err(e) = save_words(Path::new(wordfile), &words) {
-                    println!("Couldn't save vocabulary list: {}", e);
-                }
+    let config = training_config(&matches);
+    let start_time;
+    let model = match Architecture::from_name(&config.trainer) {
+        Some(architecture) => {
+            if let Some(flag) = COUNTING_FLAGS.iter().find(|&&f| matches.is_present(f)) {
+                println!("--{} only applies to co-occurrence counts, which the {} trainer \
+                          doesn't use",
+                         flag.to_lowercase(),
+                         config.trainer);
+                return;
             }
-            let builder = create_model(&corpus, words.into_iter().map(|x| x.0).collect());
-            if let Some(save) = matches.value_of("SAVE") {
-                if let Err(e) = builder.save(Path::new(save)) {
-                    println!("Couldn't save model: {}", e);
+            let corpus = match matches.value_of("CORPUS") {
+                Some(corpus) => corpus,
+                None => {
+                    println!("The {} trainer reads the corpus itself, so needs a corpus \
+                              directory location",
+                             config.trainer);
+                    return;
                 }
-            }
-
-            builder
+            };
+            start_time = time::get_time();
+            word2vec(corpus, architecture, &config, &matches)
         }
-        _ => {
-            println!("You must specify either a model to load or a corpus directory location");
-            return;
+        None => {
+            let builder = match count(&matches) {
+                Some(builder) => builder,
+                None => return,
+            };
+            start_time = time::get_time();
+            builder.build_with_progress(&config, report)
         }
     };
-
-    let start_time = time::get_time();
-    let model = builder.build();
     println!("Model built in {}s", time::get_time().sec - start_time.sec);
 
     loop {
@@ -122,6 +353,359 @@ fn main() {
 
 }
 
+fn report(progress: &Progress) {
+    println!("Epoch {}: loss {:.6} after {}s",
+             progress.epoch + 1,
+             progress.loss,
+             progress.elapsed.as_secs());
+}
+
+/// Flags that choose the vocabulary and window settings of a new builder. A loaded or merged
+/// builder keeps the ones it was counted with, so these can't be given alongside one.
+const NEW_BUILDER_FLAGS: &'static [&'static str] = &["WINDOW_RADIUS", "LEFT_RADIUS",
+                                                     "RIGHT_RADIUS", "POSITIONAL", "WEIGHTING",
+                                                     "WINDOW_SCOPE", "SEED", "FIRST_DOCUMENT",
+                                                     "NUM_WORDS", "NUM_CONTEXTS", "LOAD_WORDS"];
+
+/// Flags that only affect how co-occurrences are counted, saved or loaded. Word2vec trains on
+/// the corpus directly, so can't be given any of them.
+const COUNTING_FLAGS: &'static [&'static str] = &["LOAD", "MERGE", "SAVE", "MEMORY_BUDGET",
+                                                  "WEIGHTING", "POSITIONAL", "LEFT_RADIUS",
+                                                  "RIGHT_RADIUS", "WINDOW_SCOPE",
+                                                  "FIRST_DOCUMENT", "NUM_CONTEXTS"];
+
+/// Loads, merges or counts the builder the command line asks for.
+fn count(matches: &ArgMatches) -> Option<LanguageModelBuilder> {
+    let (load, corpus) = (matches.value_of("LOAD"), matches.value_of("CORPUS"));
+    if load.is_some() || matches.is_present("MERGE") {
+        if let Some(flag) = NEW_BUILDER_FLAGS.iter().find(|&&f| matches.is_present(f)) {
+            println!("--{} can't be used with --load or --merge, which keep the settings the \
+                      saved models were counted with",
+                     flag.to_lowercase());
+            return None;
+        }
+        if corpus.is_some() && matches.is_present("TOKENIZER") {
+            println!("Warning: --tokenizer must be the one the loaded model was counted with, \
+                      or its words won't be recognised");
+        }
+    }
+    let builder = match (load, corpus) {
+        (None, None) if matches.is_present("MERGE") => {
+            let mut builder = None;
+            if let Some(files) = matches.values_of("MERGE") {
+                for file in files {
+                    let other = LanguageModelBuilder::load(Path::new(file))
+                                    .expect("Couldn't load model");
+                    builder = match builder {
+                        None => Some(other),
+                        Some(mut builder) => {
+                            if let Err(e) = builder.merge(other) {
+                                panic!("Couldn't merge {}: {}", file, e);
+                            }
+                            Some(builder)
+                        }
+                    }
+                }
+            }
+            let builder = builder.unwrap();
+            save_builder(&builder, matches);
+
+            builder
+        }
+        (Some(l), None) => LanguageModelBuilder::load(Path::new(&l)).expect("Couldn't load model"),
+        (load, Some(corpus)) => {
+            let corpus = find_corpus_files(corpus, matches);
+            let (threads, tokenizer, segmenter) = reading_options(matches);
+
+            // a loaded builder keeps its own vocabulary and window settings, and carries on
+            // counting from where it left off
+            let mut builder = match load {
+                Some(l) => LanguageModelBuilder::load(Path::new(l)).expect("Couldn't load model"),
+                None => new_builder(&corpus, &*tokenizer, threads, matches),
+            };
+            if let Some(budget) = matches.value_of("MEMORY_BUDGET") {
+                let megabytes: usize = budget.parse().expect("Memory budget was invalid");
+                let dir = matches.value_of("SPILL_DIR")
+                                 .map(PathBuf::from)
+                                 .unwrap_or_else(env::temp_dir);
+                builder = builder.memory_budget(megabytes * 1024 * 1024, &dir);
+            }
+            let builder = create_model(&corpus,
+                                       builder,
+                                       &*tokenizer,
+                                       &segmenter,
+                                       threads);
+            save_builder(&builder, matches);
+
+            builder
+        }
+        _ => {
+            println!("You must specify either a model to load, models to merge or a corpus \
+                      directory location");
+            return None;
+        }
+    };
+    Some(builder)
+}
+
+/// Trains word2vec straight from the corpus, with the same vocabulary a builder would have.
+fn word2vec(corpus: &str,
+            architecture: Architecture,
+            config: &TrainingConfig,
+            matches: &ArgMatches)
+            -> LanguageModel {
+    let corpus = find_corpus_files(corpus, matches);
+    let (threads, tokenizer, segmenter) = reading_options(matches);
+    let words = vocabulary(&corpus, &*tokenizer, threads, matches, num_words(matches));
+    train_word2vec(&corpus,
+                   &words,
+                   architecture,
+                   window_radius(matches),
+                   &*tokenizer,
+                   &segmenter,
+                   config,
+                   seed(matches),
+                   &mut report)
+}
+
+/// The number of threads to read the corpus with, and how to split it into words and
+/// sentences.
+fn reading_options(matches: &ArgMatches) -> (usize, Box<Tokenizer>, Segmenter) {
+    let threads = matches.value_of("THREADS")
+                         .map(|n| n.parse().expect("Number of threads was invalid"))
+                         .unwrap_or(1);
+    let tokenizer = processing::tokenizer(matches.value_of("TOKENIZER").unwrap_or("ascii"))
+                        .expect("Tokenizer must be either 'ascii' or 'unicode'");
+    let segmentation = matches.value_of("SEGMENT")
+                              .map(|s| {
+                                  processing::segmentation(s)
+                                      .expect("Segmentation must be either 'sentence' or \
+                                               'paragraph'")
+                              })
+                              .unwrap_or(Segmentation::Sentences);
+    (threads, tokenizer, Segmenter::new(segmentation))
+}
+
+/// Finds the vocabulary for a new builder, either from the corpus or a saved word list.
+fn new_builder(corpus: &[PathBuf],
+               tokenizer: &Tokenizer,
+               threads: usize,
+               matches: &ArgMatches)
+               -> LanguageModelBuilder {
+    let num_words = num_words(matches);
+    let num_contexts = matches.value_of("NUM_CONTEXTS")
+                              .map(|n| n.parse().expect("Number of contexts was invalid"))
+                              .unwrap_or(num_words);
+    let words = vocabulary(corpus, tokenizer, threads, matches, num_words.max(num_contexts));
+
+    let window_scope = match matches.value_of("WINDOW_SCOPE") {
+        None | Some("sentence") => Boundary::Sentence,
+        Some("paragraph") => Boundary::Paragraph,
+        Some("document") => Boundary::Document,
+        Some(s) => panic!("Unknown window scope '{}'", s),
+    };
+    let window_radius = window_radius(matches);
+    let left_radius = matches.value_of("LEFT_RADIUS")
+                             .map(|n| n.parse().expect("Left radius was invalid"))
+                             .unwrap_or(window_radius);
+    let right_radius = matches.value_of("RIGHT_RADIUS")
+                              .map(|n| n.parse().expect("Right radius was invalid"))
+                              .unwrap_or(window_radius);
+    if left_radius + right_radius == 0 {
+        panic!("The context window must reach at least one word to the left or right");
+    }
+    let weighting = matches.value_of("WEIGHTING")
+                           .map(|w| {
+                               ContextWeighting::from_name(w)
+                                   .unwrap_or_else(|| panic!("Unknown weighting '{}'", w))
+                           })
+                           .unwrap_or(ContextWeighting::Harmonic);
+
+    let targets = words.iter().take(num_words).map(|x| x.0.clone()).collect();
+    let contexts = words.into_iter().take(num_contexts).map(|x| x.0).collect();
+    LanguageModelBuilder::with_contexts(window_radius, targets, contexts)
+        .radii(left_radius, right_radius)
+        .positional(matches.is_present("POSITIONAL"))
+        .window_scope(window_scope)
+        .weighting(weighting)
+        .seed(seed(matches))
+        .first_document(matches.value_of("FIRST_DOCUMENT")
+                               .map(|n| n.parse().expect("First document number was invalid"))
+                               .unwrap_or(0))
+}
+
+fn num_words(matches: &ArgMatches) -> usize {
+    matches.value_of("NUM_WORDS")
+           .map(|n| n.parse().expect("Number of words was invalid"))
+           .unwrap_or(30000)
+}
+
+fn window_radius(matches: &ArgMatches) -> usize {
+    matches.value_of("WINDOW_RADIUS")
+           .map(|n| n.parse().expect("Window radius was invalid"))
+           .unwrap_or(10)
+}
+
+fn seed(matches: &ArgMatches) -> u64 {
+    matches.value_of("SEED")
+           .map(|n| n.parse().expect("Seed was invalid"))
+           .unwrap_or(0)
+}
+
+/// The `num` most common words, from the corpus or a saved word list, saved to a word list
+/// if the command line asks.
+fn vocabulary(corpus: &[PathBuf],
+              tokenizer: &Tokenizer,
+              threads: usize,
+              matches: &ArgMatches,
+              num: usize)
+              -> Vec<(String, u64)> {
+    let words = match matches.value_of("LOAD_WORDS") {
+        None => find_most_common_words(corpus, num, tokenizer, threads),
+        Some(file) => {
+            fs::File::open(file)
+                .map_err(|e| From::from(e))
+                .and_then(|f| load_most_common_words(f, num))
+                .unwrap_or_else(|e| {
+                    panic!("Error loading most vocabulary list: {}", e);
+                })
+        }
+    };
+
+    if let Some(wordfile) = matches.value_of("WORDS") {
+        if let Err(e) = save_words(Path::new(wordfile), &words) {
+            println!("Couldn't save vocabulary list: {}", e);
+        }
+    }
+    words
+}
+
+/// The training settings from the config file, if any, overridden by the command line.
+fn training_config(matches: &ArgMatches) -> TrainingConfig {
+    let mut config = match matches.value_of("CONFIG") {
+        Some(file) => {
+            TrainingConfig::load(Path::new(file))
+                .unwrap_or_else(|e| panic!("Couldn't load training config: {}", e))
+        }
+        None => TrainingConfig::default(),
+    };
+
+    if let Some(trainer) = matches.value_of("TRAINER") {
+        if !models::is_trainer(trainer) {
+            panic!("Trainer must be one of {}", TRAINERS.join(", "));
+        }
+        config.trainer = trainer.to_string();
+    }
+    if let Some(rank) = matches.value_of("RANK") {
+        config.rank = rank.parse().expect("Rank was invalid");
+    }
+    if let Some(epochs) = matches.value_of("EPOCHS") {
+        config.epochs = Some(epochs.parse().expect("Number of epochs was invalid"));
+    }
+    if let Some(improvement) = matches.value_of("MIN_IMPROVEMENT") {
+        config.min_improvement = improvement.parse().expect("Minimum improvement was invalid");
+    }
+    if let Some(budget) = matches.value_of("TIME_BUDGET") {
+        let seconds: f64 = budget.parse().expect("Time budget was invalid");
+        config.time_budget = Some(Duration::from_millis((seconds * 1000.0) as u64));
+    }
+    if let Some(rate) = matches.value_of("LEARNING_RATE") {
+        config.learning_rate = Some(rate.parse().expect("Learning rate was invalid"));
+    }
+    if let Some(x_max) = matches.value_of("X_MAX") {
+        config.x_max = x_max.parse().expect("x_max was invalid");
+    }
+    if let Some(alpha) = matches.value_of("ALPHA") {
+        config.alpha = alpha.parse().expect("Alpha was invalid");
+    }
+    if let Some(init) = matches.value_of("INIT") {
+        config.init = Init::from_name(init)
+                          .expect("Init must be 'uniform', 'gaussian' or 'xavier'");
+    }
+    if let Some(scale) = matches.value_of("INIT_SCALE") {
+        config.init_scale = scale.parse().expect("Initial scale was invalid");
+    }
+    if let Some(regularisation) = matches.value_of("REGULARISATION") {
+        config.regularisation = regularisation.parse().expect("Regularisation was invalid");
+    }
+    if let Some(smoothing) = matches.value_of("SMOOTHING") {
+        config.smoothing = smoothing.parse().expect("Smoothing was invalid");
+    }
+    if let Some(weight) = matches.value_of("EIGENVALUE_WEIGHT") {
+        config.eigenvalue_weight = weight.parse().expect("Eigenvalue weight was invalid");
+    }
+    if let Some(negative) = matches.value_of("NEGATIVE") {
+        config.negative = negative.parse().expect("Number of negative samples was invalid");
+    }
+    if let Some(subsample) = matches.value_of("SUBSAMPLE") {
+        config.subsample = subsample.parse().expect("Subsampling threshold was invalid");
+    }
+    if matches.is_present("SUBWORDS") {
+        config.subwords = true;
+    }
+    if let Some(min_n) = matches.value_of("MIN_N") {
+        config.min_n = min_n.parse().expect("Shortest n-gram length was invalid");
+    }
+    if let Some(max_n) = matches.value_of("MAX_N") {
+        config.max_n = max_n.parse().expect("Longest n-gram length was invalid");
+    }
+    if let Some(buckets) = matches.value_of("BUCKETS") {
+        config.buckets = buckets.parse().expect("Number of buckets was invalid");
+    }
+    if let Some(threads) = matches.value_of("TRAINING_THREADS") {
+        config.threads = threads.parse().expect("Number of training threads was invalid");
+    }
+    if let Some(checkpoint) = matches.value_of("CHECKPOINT") {
+        config.checkpoint = Some(PathBuf::from(checkpoint));
+    }
+    if let Some(every) = matches.value_of("CHECKPOINT_EVERY") {
+        config.checkpoint_every = every.parse().expect("Checkpoint interval was invalid");
+    }
+    if matches.is_present("RESUME") {
+        config.resume = true;
+    }
+    if let Some(vectors) = matches.value_of("VECTORS") {
+        config.vectors = Some(Vectors::from_name(vectors)
+                                  .expect("Vectors must be 'words', 'contexts' or 'sum'"));
+    }
+    if let Err(e) = config.validate() {
+        panic!("{}", e);
+    }
+    config
+}
+
+fn save_builder(builder: &LanguageModelBuilder, matches: &ArgMatches) {
+    if let Some(save) = matches.value_of("SAVE") {
+        if let Err(e) = builder.save(Path::new(save)) {
+            println!("Couldn't save model: {}", e);
+        }
+    }
+}
+
+fn find_corpus_files(root: &str, matches: &ArgMatches) -> Vec<PathBuf> {
+    let mut corpus = Corpus::new(root);
+    if let Some(patterns) = matches.values_of("INCLUDE") {
+        for pattern in patterns {
+            corpus = corpus.include(pattern);
+        }
+    }
+    if let Some(patterns) = matches.values_of("EXCLUDE") {
+        for pattern in patterns {
+            corpus = corpus.exclude(pattern);
+        }
+    }
+
+    let found = corpus.walk();
+    for skipped in &found.skipped {
+        println!("Skipped {}: {}", skipped.path.display(), skipped.reason);
+    }
+    println!("Found {} corpus files, skipped {}",
+             found.files.len(),
+             found.skipped.len());
+
+    found.files
+}
+
 fn get_line() -> String {
     let stdin = stdin();
     let mut buffer = String::new();
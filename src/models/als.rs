@@ -0,0 +1,220 @@
+use models::config::{TrainingConfig, Monitor, Progress};
+use models::cooccur::Cooccurrences;
+use models::glove::weight;
+use models::linalg::{Matrix, gram, cholesky_solve};
+use models::random::{seeded_rng, INIT_STREAM};
+use std::thread;
+
+/// GloVe's weighted least squares problem, solved by alternating least squares rather than
+/// stochastic gradient descent. Holding the context vectors fixed, the best vector and bias
+/// for each word is a small linear least squares problem of its own; then the same goes for
+/// the contexts with the words held fixed. Each half-step lowers the loss, there's no
+/// learning rate to tune, and the result doesn't depend on the number of threads.
+pub struct Als {
+    pub words: Matrix,
+    pub contexts: Matrix,
+    pub word_biases: Vec<f32>,
+    pub context_biases: Vec<f32>,
+}
+
+/// The non-zero cells of one row (or column) of the co-occurrence matrix, as the index of
+/// the column (or row) and the count.
+type Line = Vec<(u32, f32)>;
+
+impl Als {
+    /// A model with context vectors drawn at random from `seed`. The word vectors are
+    /// solved for first, so their starting values don't matter.
+    pub fn new(words: usize, contexts: usize, config: &TrainingConfig, seed: u64) -> Als {
+        let rank = config.rank;
+        let scale = config.init_scale / rank as f32;
+        let mut rng = seeded_rng(seed, INIT_STREAM, 0);
+        Als {
+            words: Matrix::from_elem(words, rank, 0.0),
+            contexts: Matrix::random(contexts, rank, config.init, scale, &mut rng),
+            word_biases: vec![0.0; words],
+            context_biases: vec![0.0; contexts],
+        }
+    }
+
+    /// Solves for every word with the contexts fixed, then every context with the words
+    /// fixed, and returns the mean weighted cost over the cells of `rows`. The cost includes
+    /// the regularisation, since that's what each solve minimises, so it never goes up.
+    pub fn epoch(&mut self, rows: &[Line], cols: &[Line], config: &TrainingConfig) -> f32 {
+        solve(&mut self.words,
+              &mut self.word_biases,
+              &self.contexts,
+              &self.context_biases,
+              rows,
+              config);
+        solve(&mut self.contexts,
+              &mut self.context_biases,
+              &self.words,
+              &self.word_biases,
+              cols,
+              config);
+
+        let (mut cost, mut cells) = (0.0, 0);
+        for (row, line) in rows.iter().enumerate() {
+            for &(col, x) in line {
+                let col = col as usize;
+                let diff = &self.words[row] * &self.contexts[col] + self.word_biases[row] +
+                           self.context_biases[col] - x.ln();
+                cost += 0.5 * (weight(x, config.x_max, config.alpha) * diff * diff) as f64;
+                cells += 1;
+            }
+        }
+        let lengths = squared_length(&self.words, &self.word_biases) +
+                      squared_length(&self.contexts, &self.context_biases);
+        cost += 0.5 * config.regularisation as f64 * lengths;
+        (cost / cells.max(1) as f64) as f32
+    }
+}
+
+fn squared_length(vectors: &Matrix, biases: &[f32]) -> f64 {
+    vectors.data().iter().chain(biases.iter()).fold(0.0, |sum, &x| sum + (x * x) as f64)
+}
+
+/// Finds the best vector and bias for each of `lines`, given the `fixed` vectors and biases
+/// on the other side, splitting the lines between `config.threads` threads.
+fn solve(vectors: &mut Matrix,
+         biases: &mut [f32],
+         fixed: &Matrix,
+         fixed_biases: &[f32],
+         lines: &[Line],
+         config: &TrainingConfig) {
+    let threads = config.threads.max(1);
+    let part = ((lines.len() + threads - 1) / threads).max(1);
+    let solutions: Vec<Vec<f64>> = thread::scope(|scope| {
+        let handles: Vec<_> = lines.chunks(part)
+                                   .map(|part| {
+                                       scope.spawn(move || {
+                                           part.iter()
+                                               .map(|line| {
+                                                   solve_line(line, fixed, fixed_biases, config)
+                                               })
+                                               .collect::<Vec<_>>()
+                                       })
+                                   })
+                                   .collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    });
+
+    let rank = config.rank;
+    for (i, solution) in solutions.into_iter().enumerate() {
+        for k in 0..rank {
+            vectors[i][k] = solution[k] as f32;
+        }
+        biases[i] = solution[rank] as f32;
+    }
+}
+
+/// The vector and bias, as one vector with the bias last, that minimise the weighted error
+/// over the cells of `line`, plus `config.regularisation` times their squared length.
+///
+/// With the other side's vector `c` and bias `d` held fixed, each cell's prediction is
+/// `[w, b] . [c, 1] + d`, which is linear in the unknowns, so they solve the normal
+/// equations `(sum(f [c, 1] [c, 1]^T) + lambda I) [w, b] = sum(f (log x - d) [c, 1])`.
+fn solve_line(line: &Line,
+              fixed: &Matrix,
+              fixed_biases: &[f32],
+              config: &TrainingConfig)
+              -> Vec<f64> {
+    let n = config.rank + 1;
+    let features: Vec<Vec<f64>> = line.iter()
+                                      .map(|&(j, _)| {
+                                          let row = &fixed[j as usize];
+                                          row.iter()
+                                             .map(|&x| x as f64)
+                                             .chain(Some(1.0))
+                                             .collect()
+                                      })
+                                      .collect();
+    let weights: Vec<f64> = line.iter()
+                                .map(|&(_, x)| weight(x, config.x_max, config.alpha) as f64)
+                                .collect();
+
+    let mut a = gram(weights.iter().cloned().zip(features.iter().map(|f| &f[..])), n);
+    for i in 0..n {
+        a[i * n + i] += config.regularisation as f64;
+    }
+    let mut b = vec![0.0; n];
+    for ((&(j, x), feature), &w) in line.iter().zip(features.iter()).zip(weights.iter()) {
+        let target = w * ((x as f64).ln() - fixed_biases[j as usize] as f64);
+        for (y, f) in b.iter_mut().zip(feature.iter()) {
+            *y += target * f;
+        }
+    }
+
+    if !cholesky_solve(&mut a, &mut b) {
+        // only possible with no regularisation and too few cells to pin the row down
+        return vec![0.0; n];
+    }
+    b
+}
+
+/// Fits word and context vectors to `cooccurrences` by alternating least squares, starting
+/// from contexts drawn from `seed` and reporting the loss after each epoch to `progress`.
+pub fn train(cooccurrences: &Cooccurrences,
+             config: &TrainingConfig,
+             seed: u64,
+             progress: &mut FnMut(&Progress))
+             -> Als {
+    let mut rows = vec![vec![]; cooccurrences.rows()];
+    let mut cols = vec![vec![]; cooccurrences.cols()];
+    for (row, col, x) in cooccurrences.nonzero() {
+        rows[row as usize].push((col, x));
+        cols[col as usize].push((row, x));
+    }
+
+    let mut als = Als::new(cooccurrences.rows(), cooccurrences.cols(), config, seed);
+    let mut monitor = Monitor::new(config, progress);
+    for epoch in 0..config.epochs() {
+        let loss = als.epoch(&rows, &cols, config);
+        if !monitor.epoch_done(epoch, loss) {
+            break;
+        }
+    }
+    als
+}
+
+#[cfg(test)]
+mod test {
+    use super::train;
+    use models::config::TrainingConfig;
+    use models::cooccur::Cooccurrences;
+
+    fn counts() -> Cooccurrences {
+        let mut cooc = Cooccurrences::new(4, 4);
+        for &(row, col, x) in &[(0, 1, 10.0), (1, 0, 10.0), (0, 2, 2.0), (2, 0, 2.0),
+                                (1, 2, 4.0), (2, 1, 4.0), (2, 3, 7.0), (3, 2, 7.0),
+                                (3, 3, 1.0)] {
+            cooc.add(row, col, x);
+        }
+        cooc
+    }
+
+    #[test]
+    fn test_fits_counts() {
+        let config = TrainingConfig {
+            rank: 3,
+            epochs: Some(20),
+            min_improvement: 0.001,
+            regularisation: 0.0001,
+            ..TrainingConfig::default()
+        };
+        let mut losses = vec![];
+        let als = train(&counts(), &config, 1, &mut |p| losses.push(p.loss));
+        // every half-step solves exactly, so the loss can only fall
+        assert!(losses.windows(2).all(|pair| pair[1] <= pair[0] * 1.00001));
+        let last = losses[losses.len() - 1];
+        assert!(last < losses[0] / 2.0 && last < 0.001);
+
+        // solving each row is independent, so the threads don't change the answer
+        let threaded = train(&counts(),
+                             &TrainingConfig { threads: 3, ..config.clone() },
+                             1,
+                             &mut |_| {});
+        assert_eq!(als.words, threaded.words);
+        assert_eq!(als.context_biases, threaded.context_biases);
+    }
+}
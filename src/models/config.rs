@@ -0,0 +1,443 @@
+use error::Error;
+use models::linalg::Init;
+use models::models::Vectors;
+use models::trainer::{is_trainer, TRAINERS};
+use models::word2vec::Architecture;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use yaml_rust::{Yaml, YamlLoader};
+
+/// The hyperparameters for training a model from a builder's counts.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TrainingConfig {
+    /// The name of the trainer to fit the vectors with: one known to `trainer::trainer`, or
+    /// 'skipgram' or 'cbow' to train word2vec on the corpus itself rather than on counts.
+    pub trainer: String,
+    /// The number of dimensions of the trained vectors.
+    pub rank: usize,
+    /// The most epochs to train for. When unset, it's 5 for skip-gram and CBOW, as in
+    /// word2vec, and 100 for the trainers that pass over the counts.
+    pub epochs: Option<usize>,
+    /// Training stops once an epoch lowers the loss by less than this fraction. With zero,
+    /// the default, it never stops early, since stochastic training often raises the loss
+    /// for an epoch or two on its way down.
+    pub min_improvement: f32,
+    /// Training stops after the first epoch to end past this much time.
+    pub time_budget: Option<Duration>,
+    /// The learning rate training starts from: AdaGrad's base rate for GloVe, and the rate
+    /// word2vec lowers towards zero. When unset, it's 0.025 for skip-gram, as in word2vec,
+    /// and 0.05 otherwise.
+    pub learning_rate: Option<f32>,
+    /// Co-occurrence counts above this get full weight in the loss.
+    pub x_max: f32,
+    /// The exponent of the weighting function below `x_max`.
+    pub alpha: f32,
+    /// How the vectors' starting values are drawn.
+    pub init: Init,
+    /// The spread of the starting values: uniform ones lie within `init_scale / rank` of
+    /// zero, and Gaussian ones have that standard deviation. Xavier ignores it.
+    pub init_scale: f32,
+    /// Which vectors the model keeps. When unset, word2vec keeps only the word vectors, as
+    /// it usually does, and the other trainers keep the sum of word and context vectors.
+    pub vectors: Option<Vectors>,
+    /// For ALS, how much the squared lengths of the vectors and biases add to the loss,
+    /// which keeps rows with few counts from fitting them exactly.
+    pub regularisation: f32,
+    /// For SVD, the power context counts are raised to before taking their probabilities.
+    pub smoothing: f32,
+    /// For SVD, the power of the singular values that vectors are scaled by.
+    pub eigenvalue_weight: f32,
+    /// For word2vec, how many negative samples to draw for each prediction.
+    pub negative: usize,
+    /// For word2vec, words making up more than this fraction of the corpus are randomly
+    /// dropped in proportion to how common they are. Zero keeps every word.
+    pub subsample: f32,
+    /// For word2vec, also learns vectors for the character n-grams of words, from which a
+    /// vector can be made for any word, even one that wasn't in the vocabulary.
+    pub subwords: bool,
+    /// The shortest n-grams to learn vectors for, counting the marks added at the start
+    /// and end of each word.
+    pub min_n: usize,
+    /// The longest n-grams to learn vectors for.
+    pub max_n: usize,
+    /// How many vectors the n-grams are hashed into.
+    pub buckets: usize,
+    /// How many threads to train with. More than one makes training faster, but no longer
+    /// exactly reproducible.
+    pub threads: usize,
+    /// Where to save checkpoints of training, if anywhere. Only GloVe training saves them.
+    pub checkpoint: Option<PathBuf>,
+    /// How many epochs to train for between checkpoints.
+    pub checkpoint_every: usize,
+    /// Carries on from the checkpoint, if there is one, rather than starting afresh.
+    pub resume: bool,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> TrainingConfig {
+        TrainingConfig {
+            trainer: "glove".to_string(),
+            rank: 200,
+            epochs: None,
+            min_improvement: 0.0,
+            time_budget: None,
+            learning_rate: None,
+            x_max: 100.0,
+            alpha: 0.75,
+            init: Init::Uniform,
+            init_scale: 0.5,
+            vectors: None,
+            regularisation: 0.01,
+            smoothing: 0.75,
+            eigenvalue_weight: 0.5,
+            negative: 5,
+            subsample: 0.001,
+            subwords: false,
+            min_n: 3,
+            max_n: 6,
+            buckets: 100000,
+            threads: 1,
+            checkpoint: None,
+            checkpoint_every: 10,
+            resume: false,
+        }
+    }
+}
+
+impl TrainingConfig {
+    /// Reads a configuration from a YAML mapping such as
+    ///
+    /// ```yaml
+    /// rank: 300
+    /// epochs: 50
+    /// vectors: words
+    /// ```
+    ///
+    /// Anything left out keeps its default value.
+    pub fn from_yaml(text: &str) -> Result<TrainingConfig, Error> {
+        let docs = try!(YamlLoader::load_from_str(text)
+                            .map_err(|e| Error::Config(format!("{}", e))));
+        let mut config = TrainingConfig::default();
+        let doc = match docs.into_iter().next() {
+            None | Some(Yaml::Null) => return Ok(config),
+            Some(doc) => doc,
+        };
+        let fields = match doc {
+            Yaml::Hash(fields) => fields,
+            _ => return Err(Error::Config("expected a mapping of settings".to_string())),
+        };
+
+        for (key, value) in fields {
+            let key = match key {
+                Yaml::String(key) => key,
+                _ => return Err(Error::Config(format!("invalid setting {:?}", key))),
+            };
+            try!(config.set(&key, &value));
+        }
+        try!(config.validate());
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<TrainingConfig, Error> {
+        let mut text = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut text));
+        TrainingConfig::from_yaml(&text)
+    }
+
+    /// The learning rate to train with, the trainer's own default if none was set.
+    pub fn learning_rate(&self) -> f32 {
+        self.learning_rate.unwrap_or(if self.trainer == "skipgram" { 0.025 } else { 0.05 })
+    }
+
+    /// The most epochs to train for, the trainer's own default if none was set.
+    pub fn epochs(&self) -> usize {
+        self.epochs.unwrap_or(if Architecture::from_name(&self.trainer).is_some() {
+            5
+        } else {
+            100
+        })
+    }
+
+    /// The vectors to keep, the trainer's own default if none was set.
+    pub fn vectors(&self) -> Vectors {
+        self.vectors.unwrap_or(if Architecture::from_name(&self.trainer).is_some() {
+            Vectors::Words
+        } else {
+            Vectors::Sum
+        })
+    }
+
+    /// Checks for settings that training can't work with at all, as opposed to ones that
+    /// merely train badly.
+    pub fn validate(&self) -> Result<(), Error> {
+        for &(key, value) in &[("rank", self.rank),
+                               ("epochs", self.epochs()),
+                               ("negative", self.negative),
+                               ("threads", self.threads)] {
+            if value == 0 {
+                return Err(Error::Config(format!("'{}' must be at least 1", key)));
+            }
+        }
+        if self.subwords {
+            for &(key, value) in &[("buckets", self.buckets), ("min_n", self.min_n)] {
+                if value == 0 {
+                    return Err(Error::Config(format!("'{}' must be at least 1", key)));
+                }
+            }
+            if self.min_n > self.max_n {
+                return Err(Error::Config("'min_n' can't be more than 'max_n'".to_string()));
+            }
+        }
+        for &(key, value) in &[("x_max", self.x_max), ("init_scale", self.init_scale)] {
+            if !(value > 0.0) {
+                return Err(Error::Config(format!("'{}' must be positive", key)));
+            }
+        }
+        if self.subwords {
+            if Architecture::from_name(&self.trainer).is_none() {
+                let message = "only the skipgram and cbow trainers learn subword vectors";
+                return Err(Error::Config(message.to_string()));
+            }
+            // a word made up from its n-grams is only comparable with the word vectors
+            if self.vectors() != Vectors::Words {
+                return Err(Error::Config("'subwords' needs 'vectors: words'".to_string()));
+            }
+        }
+        if (self.checkpoint.is_some() || self.resume) && self.trainer != "glove" {
+            return Err(Error::Config(format!("the '{}' trainer can't checkpoint or resume",
+                                             self.trainer)));
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &Yaml) -> Result<(), Error> {
+        match key {
+            "trainer" => {
+                let name = try!(value.as_str().ok_or_else(|| invalid(key, value)));
+                if !is_trainer(name) {
+                    return Err(Error::Config(format!("'trainer' must be one of {}",
+                                                     TRAINERS.join(", "))));
+                }
+                self.trainer = name.to_string();
+            }
+            "rank" => self.rank = try!(integer(key, value)),
+            "epochs" => self.epochs = Some(try!(integer(key, value))),
+            "min_improvement" => self.min_improvement = try!(real(key, value)),
+            "time_budget" => {
+                let seconds = try!(real(key, value));
+                self.time_budget = Some(Duration::from_millis((seconds * 1000.0) as u64));
+            }
+            "learning_rate" => self.learning_rate = Some(try!(real(key, value))),
+            "x_max" => self.x_max = try!(real(key, value)),
+            "alpha" => self.alpha = try!(real(key, value)),
+            "init" => {
+                self.init = try!(value.as_str()
+                                      .and_then(Init::from_name)
+                                      .ok_or_else(|| invalid(key, value)))
+            }
+            "init_scale" => self.init_scale = try!(real(key, value)),
+            "regularisation" => self.regularisation = try!(real(key, value)),
+            "smoothing" => self.smoothing = try!(real(key, value)),
+            "eigenvalue_weight" => self.eigenvalue_weight = try!(real(key, value)),
+            "negative" => self.negative = try!(integer(key, value)),
+            "subsample" => self.subsample = try!(real(key, value)),
+            "subwords" => {
+                self.subwords = try!(value.as_bool().ok_or_else(|| invalid(key, value)))
+            }
+            "min_n" => self.min_n = try!(integer(key, value)),
+            "max_n" => self.max_n = try!(integer(key, value)),
+            "buckets" => self.buckets = try!(integer(key, value)),
+            "threads" => self.threads = try!(integer(key, value)),
+            "checkpoint" => {
+                let path = try!(value.as_str().ok_or_else(|| invalid(key, value)));
+                self.checkpoint = Some(PathBuf::from(path));
+            }
+            "checkpoint_every" => self.checkpoint_every = try!(integer(key, value)),
+            "resume" => self.resume = try!(value.as_bool().ok_or_else(|| invalid(key, value))),
+            "vectors" => {
+                self.vectors = Some(try!(value.as_str()
+                                              .and_then(Vectors::from_name)
+                                              .ok_or_else(|| invalid(key, value))))
+            }
+            _ => return Err(Error::Config(format!("unknown setting '{}'", key))),
+        }
+        Ok(())
+    }
+}
+
+/// How training is going, reported after every epoch.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    /// Counting from zero.
+    pub epoch: usize,
+    /// The mean weighted loss over the epoch.
+    pub loss: f32,
+    /// The time since training started.
+    pub elapsed: Duration,
+}
+
+/// Reports progress after each epoch and decides when training should stop, according
+/// to a config's epoch limit, loss improvement threshold and time budget.
+pub struct Monitor<'a> {
+    config: &'a TrainingConfig,
+    progress: &'a mut FnMut(&Progress),
+    start: Instant,
+    last_loss: Option<f32>,
+}
+
+impl<'a> Monitor<'a> {
+    pub fn new(config: &'a TrainingConfig,
+               progress: &'a mut FnMut(&Progress))
+               -> Monitor<'a> {
+        Monitor {
+            config: config,
+            progress: progress,
+            start: Instant::now(),
+            last_loss: None,
+        }
+    }
+
+    /// Carries on from training that stopped with the given loss.
+    pub fn resume(&mut self, last_loss: Option<f32>) {
+        self.last_loss = last_loss;
+    }
+
+    /// Records the loss of an epoch that has just finished, returning whether to carry on
+    /// training.
+    pub fn epoch_done(&mut self, epoch: usize, loss: f32) -> bool {
+        let elapsed = self.start.elapsed();
+        (self.progress)(&Progress {
+            epoch: epoch,
+            loss: loss,
+            elapsed: elapsed,
+        });
+
+        let converged = match self.last_loss {
+            Some(last) if last > 0.0 && self.config.min_improvement > 0.0 => {
+                (last - loss) / last < self.config.min_improvement
+            }
+            _ => false,
+        };
+        self.last_loss = Some(loss);
+
+        let out_of_time = self.config.time_budget.map(|b| elapsed >= b).unwrap_or(false);
+        epoch + 1 < self.config.epochs() && !converged && !out_of_time
+    }
+}
+
+fn integer(key: &str, value: &Yaml) -> Result<usize, Error> {
+    match value.as_i64() {
+        Some(n) if n >= 0 => Ok(n as usize),
+        _ => Err(invalid(key, value)),
+    }
+}
+
+fn real(key: &str, value: &Yaml) -> Result<f32, Error> {
+    match *value {
+        Yaml::Integer(n) => Ok(n as f32),
+        _ => value.as_f64().map(|x| x as f32).ok_or_else(|| invalid(key, value)),
+    }
+}
+
+fn invalid(key: &str, value: &Yaml) -> Error {
+    Error::Config(format!("invalid value {:?} for '{}'", value, key))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TrainingConfig, Monitor};
+    use models::linalg::Init;
+    use models::models::Vectors;
+    use std::time::Duration;
+
+    #[test]
+    fn test_from_yaml() {
+        let config = TrainingConfig::from_yaml("rank: 50\nlearning_rate: 0.1\nalpha: 1\n\
+                                                vectors: contexts\ninit: xavier\n\
+                                                time_budget: 1.5\ntrainer: svd\n\
+                                                negative: 10\n")
+                         .unwrap();
+        assert_eq!(config,
+                   TrainingConfig {
+                       rank: 50,
+                       learning_rate: Some(0.1),
+                       alpha: 1.0,
+                       vectors: Some(Vectors::Contexts),
+                       init: Init::Xavier,
+                       time_budget: Some(Duration::from_millis(1500)),
+                       trainer: "svd".to_string(),
+                       negative: 10,
+                       ..TrainingConfig::default()
+                   });
+
+        assert_eq!(TrainingConfig::from_yaml("").unwrap(), TrainingConfig::default());
+
+        let skipgram = TrainingConfig::from_yaml("trainer: skipgram").unwrap();
+        assert_eq!((skipgram.learning_rate(), skipgram.vectors()), (0.025, Vectors::Words));
+        assert_eq!(skipgram.epochs(), 5);
+        let glove = TrainingConfig::default();
+        assert_eq!((glove.learning_rate(), glove.vectors()), (0.05, Vectors::Sum));
+        assert_eq!(glove.epochs(), 100);
+        assert!(TrainingConfig::from_yaml("rank: many").is_err());
+        assert!(TrainingConfig::from_yaml("epochs: -1").is_err());
+        assert!(TrainingConfig::from_yaml("{trainer: cbow, epochs: 0}").is_err());
+        assert!(TrainingConfig::from_yaml("rank: 0").is_err());
+        assert!(TrainingConfig::from_yaml("threads: 0").is_err());
+        assert!(TrainingConfig::from_yaml("x_max: 0").is_err());
+        assert!(TrainingConfig::from_yaml("init_scale: 0").is_err());
+        assert!(TrainingConfig::from_yaml("{init: gaussian, init_scale: -0.1}").is_err());
+        assert!(TrainingConfig::from_yaml("{trainer: svd, resume: true}").is_err());
+        assert!(TrainingConfig::from_yaml("{trainer: cbow, subwords: true}").is_ok());
+        assert!(TrainingConfig::from_yaml("{trainer: cbow, subwords: true, vectors: sum}")
+                    .is_err());
+        assert!(TrainingConfig::from_yaml("{trainer: glove, subwords: true}").is_err());
+        for bad in &["buckets: 0", "min_n: 0", "min_n: 4, max_n: 3"] {
+            let yaml = format!("{{trainer: cbow, subwords: true, {}}}", bad);
+            assert!(TrainingConfig::from_yaml(&yaml).is_err());
+        }
+        assert!(TrainingConfig::from_yaml("learning_rte: 0.1").is_err());
+        assert!(TrainingConfig::from_yaml("trainer: word2vec").is_err());
+        assert!(TrainingConfig::from_yaml("- rank").is_err());
+    }
+
+    #[test]
+    fn test_stopping() {
+        let config = TrainingConfig {
+            epochs: Some(4),
+            min_improvement: 0.1,
+            ..TrainingConfig::default()
+        };
+        let mut losses = vec![];
+        {
+            let mut record = |p: &super::Progress| losses.push((p.epoch, p.loss));
+            let mut monitor = Monitor::new(&config, &mut record);
+            assert!(monitor.epoch_done(0, 10.0));
+            assert!(monitor.epoch_done(1, 5.0));
+            assert!(!monitor.epoch_done(2, 4.8));
+        }
+        assert_eq!(losses, vec![(0, 10.0), (1, 5.0), (2, 4.8)]);
+
+        let mut ignore = |_: &super::Progress| {};
+        let mut monitor = Monitor::new(&config, &mut ignore);
+        for epoch in 0..3 {
+            assert!(monitor.epoch_done(epoch, 1.0 / (epoch + 1) as f32));
+        }
+        assert!(!monitor.epoch_done(3, 0.1));
+
+        // without a threshold, a loss that goes up doesn't stop training
+        let config = TrainingConfig { epochs: Some(4), ..TrainingConfig::default() };
+        let mut monitor = Monitor::new(&config, &mut ignore);
+        assert!(monitor.epoch_done(0, 1.0));
+        assert!(monitor.epoch_done(1, 2.0));
+        assert!(monitor.epoch_done(2, 2.0));
+        assert!(!monitor.epoch_done(3, 0.5));
+
+        let config = TrainingConfig {
+            time_budget: Some(Duration::from_millis(0)),
+            ..TrainingConfig::default()
+        };
+        assert!(!Monitor::new(&config, &mut ignore).epoch_done(0, 1.0));
+    }
+}
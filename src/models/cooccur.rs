@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+/// Weighted co-occurrence counts between target words (rows) and context words (columns).
+///
+/// Most pairs of words never appear near each other, so each row only stores its non-zero
+/// cells. Columns are kept as `u32` to halve the size of every entry.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cooccurrences {
+    cols: usize,
+    cells: usize,
+    rows: Vec<HashMap<u32, f32>>,
+}
+
+impl Cooccurrences {
+    pub fn new(rows: usize, cols: usize) -> Cooccurrences {
+        assert!(cols <= u32::max_value() as usize);
+        Cooccurrences {
+            cols: cols,
+            cells: 0,
+            rows: (0..rows).map(|_| HashMap::new()).collect(),
+        }
+    }
+
+    #[inline]
+    pub fn add(&mut self, row: usize, col: usize, weight: f32) {
+        debug_assert!(col < self.cols);
+        match self.rows[row].entry(col as u32) {
+            Entry::Occupied(mut e) => *e.get_mut() += weight,
+            Entry::Vacant(e) => {
+                e.insert(weight);
+                self.cells += 1;
+            }
+        }
+    }
+
+    #[cfg(test)]
+    #[inline]
+    pub fn get(&self, row: usize, col: usize) -> f32 {
+        self.rows[row].get(&(col as u32)).map(|w| *w).unwrap_or(0.0)
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// The number of cells that have been stored.
+    pub fn cells(&self) -> usize {
+        self.cells
+    }
+
+    /// Adds every stored cell of `other` onto this one.
+    pub fn add_all(&mut self, other: &Cooccurrences) {
+        assert_eq!((self.rows(), self.cols()), (other.rows(), other.cols()));
+        for (row, cells) in other.rows.iter().enumerate() {
+            for (&col, &weight) in cells {
+                self.add(row, col as usize, weight);
+            }
+        }
+    }
+
+    /// Empties every row, releasing the memory that held them.
+    pub fn clear(&mut self) {
+        for row in &mut self.rows {
+            *row = HashMap::new();
+        }
+        self.cells = 0;
+    }
+
+    /// Every stored cell with a non-zero weight as a (row, column, weight) triple, ordered
+    /// by row and then column.
+    pub fn nonzero(&self) -> Vec<(u32, u32, f32)> {
+        let mut cells = Vec::with_capacity(self.cells);
+        for row in 0..self.rows() {
+            for (col, weight) in self.row(row) {
+                if weight != 0.0 {
+                    cells.push((row as u32, col as u32, weight));
+                }
+            }
+        }
+        cells
+    }
+
+    /// The stored cells of one row, ordered by column.
+    pub fn row(&self, row: usize) -> Vec<(usize, f32)> {
+        let mut cells: Vec<_> = self.rows[row].iter().map(|(&c, &w)| (c as usize, w)).collect();
+        cells.sort_by(|a, b| a.0.cmp(&b.0));
+        cells
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Cooccurrences;
+
+    #[test]
+    fn test_sparse_cells() {
+        let mut cooc = Cooccurrences::new(3, 4);
+        cooc.add(1, 3, 0.5);
+        cooc.add(1, 0, 1.0);
+        cooc.add(1, 3, 0.25);
+
+        assert_eq!(cooc.get(1, 3), 0.75);
+        assert_eq!(cooc.get(2, 3), 0.0);
+        assert_eq!(cooc.cells(), 2);
+        assert_eq!(cooc.row(1), vec![(0, 1.0), (3, 0.75)]);
+        assert_eq!(cooc.row(0), vec![]);
+    }
+}
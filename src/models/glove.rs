@@ -0,0 +1,447 @@
+use error::Error;
+use models::config::{TrainingConfig, Monitor, Progress};
+use models::cooccur::Cooccurrences;
+use models::hogwild::{AtomicF32, HogwildMatrix, share};
+use models::linalg::Matrix;
+use models::models::{read_raw, write_raw, read_byte};
+use models::random::{seeded_rng, INIT_STREAM, SHUFFLE_STREAM};
+use rand::Rng;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::thread;
+
+/// How much the error on a cell with count `x` counts for: `(x / x_max)^alpha`, capped at
+/// one. Rare co-occurrences are noisy, and very common ones shouldn't swamp the rest.
+pub fn weight(x: f32, x_max: f32, alpha: f32) -> f32 {
+    if x < x_max {
+        (x / x_max).powf(alpha)
+    } else {
+        1.0
+    }
+}
+
+/// The parameters of a GloVe model, along with the sums of squared gradients that AdaGrad
+/// scales each update by. They're all shared between the training threads, which update
+/// them through `&self`.
+///
+/// Training minimises `f(X_ij) (w_i . w~_j + b_i + b~_j - log X_ij)^2` summed over the
+/// non-zero cells of the co-occurrence matrix, where `w` are the word vectors and `w~` the
+/// context vectors.
+pub struct Glove {
+    learning_rate: f32,
+    x_max: f32,
+    alpha: f32,
+    pub words: HogwildMatrix,
+    pub contexts: HogwildMatrix,
+    pub word_biases: Vec<AtomicF32>,
+    pub context_biases: Vec<AtomicF32>,
+    word_gradsq: HogwildMatrix,
+    context_gradsq: HogwildMatrix,
+    word_bias_gradsq: Vec<AtomicF32>,
+    context_bias_gradsq: Vec<AtomicF32>,
+}
+
+impl Glove {
+    /// A model with vectors drawn at random from `seed`, as `config` says.
+    pub fn new(words: usize, contexts: usize, config: &TrainingConfig, seed: u64) -> Glove {
+        let rank = config.rank;
+        let scale = config.init_scale / rank as f32;
+        let mut rng = seeded_rng(seed, INIT_STREAM, 0);
+        Glove {
+            learning_rate: config.learning_rate(),
+            x_max: config.x_max,
+            alpha: config.alpha,
+            words: HogwildMatrix::new(&Matrix::random(words, rank, config.init, scale, &mut rng)),
+            contexts: HogwildMatrix::new(&Matrix::random(contexts,
+                                                         rank,
+                                                         config.init,
+                                                         scale,
+                                                         &mut rng)),
+            word_biases: share(&vec![0.0; words]),
+            context_biases: share(&vec![0.0; contexts]),
+            word_gradsq: HogwildMatrix::new(&Matrix::from_elem(words, rank, 1.0)),
+            context_gradsq: HogwildMatrix::new(&Matrix::from_elem(contexts, rank, 1.0)),
+            word_bias_gradsq: share(&vec![1.0; words]),
+            context_bias_gradsq: share(&vec![1.0; contexts]),
+        }
+    }
+
+    /// The model's estimate of `log X_ij`.
+    pub fn predict(&self, word: usize, context: usize) -> f32 {
+        let (w, w_ctx) = (self.words.row(word), self.contexts.row(context));
+        w.iter().zip(w_ctx.iter()).fold(0.0, |sum, (a, b)| sum + a.get() * b.get()) +
+        self.word_biases[word].get() + self.context_biases[context].get()
+    }
+
+    /// Makes one pass over the non-zero `cells` of the co-occurrence matrix, visiting them
+    /// in `order`, and returns the mean weighted cost over the pass.
+    ///
+    /// With more than one thread, `order` is split between them and they all update the
+    /// vectors at once without any locking, as in Hogwild. Two threads will rarely touch the
+    /// same vector at the same time, and when they do one update may overwrite the other,
+    /// which loses a little progress but nothing worse. The result is no longer exactly
+    /// reproducible, though.
+    pub fn epoch(&self, cells: &[(u32, u32, f32)], order: &[u32], threads: usize) -> f32 {
+        let cost = if threads <= 1 {
+            self.visit(cells, order)
+        } else {
+            let part = (order.len() + threads - 1) / threads;
+            thread::scope(|scope| {
+                let handles: Vec<_> = order.chunks(part.max(1))
+                                           .map(|part| {
+                                               scope.spawn(move || self.visit(cells, part))
+                                           })
+                                           .collect();
+                handles.into_iter().map(|h| h.join().unwrap()).fold(0.0, |x, y| x + y)
+            })
+        };
+        (cost / order.len().max(1) as f64) as f32
+    }
+
+    fn visit(&self, cells: &[(u32, u32, f32)], order: &[u32]) -> f64 {
+        let mut cost = 0.0;
+        for &i in order {
+            let (row, col, x) = cells[i as usize];
+            cost += self.update(row as usize, col as usize, x) as f64;
+        }
+        cost
+    }
+
+    fn update(&self, row: usize, col: usize, x: f32) -> f32 {
+        let diff = self.predict(row, col) - x.ln();
+        let fdiff = weight(x, self.x_max, self.alpha) * diff;
+        let rate = self.learning_rate;
+
+        let (w, w_ctx) = (self.words.row(row), self.contexts.row(col));
+        let (gradsq, gradsq_ctx) = (self.word_gradsq.row(row), self.context_gradsq.row(col));
+        for k in 0..w.len() {
+            let (x, x_ctx) = (w[k].get(), w_ctx[k].get());
+            let grad = fdiff * x_ctx;
+            let grad_ctx = fdiff * x;
+            w[k].set(x - rate * grad / gradsq[k].get().sqrt());
+            w_ctx[k].set(x_ctx - rate * grad_ctx / gradsq_ctx[k].get().sqrt());
+            gradsq[k].set(gradsq[k].get() + grad * grad);
+            gradsq_ctx[k].set(gradsq_ctx[k].get() + grad_ctx * grad_ctx);
+        }
+
+        let (b, b_ctx) = (&self.word_biases[row], &self.context_biases[col]);
+        let (gradsq, gradsq_ctx) = (&self.word_bias_gradsq[row], &self.context_bias_gradsq[col]);
+        b.set(b.get() - rate * fdiff / gradsq.get().sqrt());
+        b_ctx.set(b_ctx.get() - rate * fdiff / gradsq_ctx.get().sqrt());
+        gradsq.set(gradsq.get() + fdiff * fdiff);
+        gradsq_ctx.set(gradsq_ctx.get() + fdiff * fdiff);
+
+        0.5 * fdiff * diff
+    }
+}
+
+/// Where training had got to after some number of epochs: everything needed to carry on
+/// exactly as if it had never stopped. The order cells are visited in follows from the seed
+/// and the epoch, so that stands in for the state of the random number generator.
+pub struct Checkpoint {
+    pub seed: u64,
+    /// The number of epochs completed.
+    pub epoch: usize,
+    pub last_loss: Option<f32>,
+    pub glove: Glove,
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint to `path`, by way of a temporary file so that a crash part
+    /// way through leaves any earlier checkpoint intact.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let temp = path.with_extension("partial");
+        {
+            let mut file = BufWriter::new(try!(File::create(&temp)));
+            let glove = &self.glove;
+            let (rows, cols) = (glove.words.rows(), glove.contexts.rows());
+            let rank = glove.words.cols();
+
+            try!(write_raw(self.seed, &mut file));
+            try!(write_raw(self.epoch as u64, &mut file));
+            try!(write_raw(self.last_loss.is_some() as u8, &mut file));
+            try!(write_raw(self.last_loss.unwrap_or(0.0), &mut file));
+            for &n in &[rows, cols, rank] {
+                try!(write_raw(n as u64, &mut file));
+            }
+            for &x in &[glove.learning_rate, glove.x_max, glove.alpha] {
+                try!(write_raw(x, &mut file));
+            }
+            try!(file.write(&[b'\n']));
+
+            try!(write_floats(glove.words.data(), &mut file));
+            try!(write_floats(glove.contexts.data(), &mut file));
+            try!(write_floats(&glove.word_biases, &mut file));
+            try!(write_floats(&glove.context_biases, &mut file));
+            try!(write_floats(glove.word_gradsq.data(), &mut file));
+            try!(write_floats(glove.context_gradsq.data(), &mut file));
+            try!(write_floats(&glove.word_bias_gradsq, &mut file));
+            try!(write_floats(&glove.context_bias_gradsq, &mut file));
+            try!(file.flush());
+        }
+        fs::rename(&temp, path)
+    }
+
+    /// Reads a checkpoint saved by `save`.
+    pub fn load(path: &Path) -> io::Result<Checkpoint> {
+        let mut file = BufReader::new(try!(File::open(path)));
+
+        let seed = unsafe { try!(read_raw::<u64, _>(&mut file)) };
+        let epoch = unsafe { try!(read_raw::<u64, _>(&mut file)) } as usize;
+        let has_loss = unsafe { try!(read_raw::<u8, _>(&mut file)) } != 0;
+        let loss = unsafe { try!(read_raw::<f32, _>(&mut file)) };
+        let rows = unsafe { try!(read_raw::<u64, _>(&mut file)) } as usize;
+        let cols = unsafe { try!(read_raw::<u64, _>(&mut file)) } as usize;
+        let rank = unsafe { try!(read_raw::<u64, _>(&mut file)) } as usize;
+        let learning_rate = unsafe { try!(read_raw::<f32, _>(&mut file)) };
+        let x_max = unsafe { try!(read_raw::<f32, _>(&mut file)) };
+        let alpha = unsafe { try!(read_raw::<f32, _>(&mut file)) };
+        try!(read_byte(b'\n', &mut file));
+
+        let glove = Glove {
+            learning_rate: learning_rate,
+            x_max: x_max,
+            alpha: alpha,
+            words: try!(read_matrix(rows, rank, &mut file)),
+            contexts: try!(read_matrix(cols, rank, &mut file)),
+            word_biases: share(&try!(read_floats(rows, &mut file))),
+            context_biases: share(&try!(read_floats(cols, &mut file))),
+            word_gradsq: try!(read_matrix(rows, rank, &mut file)),
+            context_gradsq: try!(read_matrix(cols, rank, &mut file)),
+            word_bias_gradsq: share(&try!(read_floats(rows, &mut file))),
+            context_bias_gradsq: share(&try!(read_floats(cols, &mut file))),
+        };
+
+        Ok(Checkpoint {
+            seed: seed,
+            epoch: epoch,
+            last_loss: if has_loss { Some(loss) } else { None },
+            glove: glove,
+        })
+    }
+
+    /// Checks that this checkpoint could have come from training on `cooccurrences` as
+    /// `config` says, starting from `seed`, and says how it differs if not.
+    pub fn check(&self,
+                 cooccurrences: &Cooccurrences,
+                 config: &TrainingConfig,
+                 seed: u64)
+                 -> Result<(), Error> {
+        let glove = &self.glove;
+        let differs = |what: &str, ours: String, theirs: String| {
+            Err(Error::Mismatch(format!("checkpoint has {} {}, not {}", what, ours, theirs)))
+        };
+        if self.seed != seed {
+            return differs("seed", self.seed.to_string(), seed.to_string());
+        }
+        let shape = (glove.words.rows(), glove.contexts.rows(), glove.words.cols());
+        let expected = (cooccurrences.rows(), cooccurrences.cols(), config.rank);
+        if shape != expected {
+            return differs("words, contexts and rank",
+                           format!("{:?}", shape),
+                           format!("{:?}", expected));
+        }
+        for &(what, ours, theirs) in &[("learning_rate",
+                                        glove.learning_rate,
+                                        config.learning_rate()),
+                                       ("x_max", glove.x_max, config.x_max),
+                                       ("alpha", glove.alpha, config.alpha)] {
+            if ours != theirs {
+                return differs(what, ours.to_string(), theirs.to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_floats<W: Write>(floats: &[AtomicF32], file: &mut W) -> io::Result<()> {
+    for x in floats {
+        try!(write_raw(x.get(), file));
+    }
+    Ok(())
+}
+
+fn read_floats<R: Read>(n: usize, file: &mut BufReader<R>) -> io::Result<Vec<f32>> {
+    let mut floats = Vec::with_capacity(n);
+    for _ in 0..n {
+        floats.push(unsafe { try!(read_raw::<f32, _>(file)) });
+    }
+    Ok(floats)
+}
+
+fn read_matrix<R: Read>(rows: usize,
+                        cols: usize,
+                        file: &mut BufReader<R>)
+                        -> io::Result<HogwildMatrix> {
+    let floats = try!(read_floats(rows * cols, file));
+    Ok(HogwildMatrix::new(&Matrix::from_vec(rows, cols, floats)))
+}
+
+/// Fills `order` with the indices of `cells` cells in the order to visit them in `epoch`.
+/// Every epoch's order follows from the seed alone, so training can pick up from any epoch.
+pub fn shuffle(order: &mut Vec<u32>, cells: usize, seed: u64, epoch: usize) {
+    assert!(cells <= u32::max_value() as usize);
+    order.clear();
+    order.extend(0..cells as u32);
+    seeded_rng(seed, SHUFFLE_STREAM, epoch as u64).shuffle(order);
+}
+
+/// Fits a GloVe model to `cooccurrences`, starting from vectors drawn from `seed`, and
+/// reporting the loss after each epoch to `progress`.
+///
+/// If the config names a checkpoint file, where training has got to is saved there every
+/// few epochs and when training stops, and with `resume` set training carries on from that
+/// checkpoint rather than starting again.
+pub fn train(cooccurrences: &Cooccurrences,
+             config: &TrainingConfig,
+             seed: u64,
+             progress: &mut FnMut(&Progress))
+             -> Glove {
+    let cells = cooccurrences.nonzero();
+    let checkpoint = match config.checkpoint {
+        Some(ref path) if config.resume && path.exists() => {
+            let checkpoint = Checkpoint::load(path).expect("Couldn't load training checkpoint");
+            if let Err(e) = checkpoint.check(cooccurrences, config, seed) {
+                panic!("Can't resume training: {}", e);
+            }
+            checkpoint
+        }
+        _ => {
+            Checkpoint {
+                seed: seed,
+                epoch: 0,
+                last_loss: None,
+                glove: Glove::new(cooccurrences.rows(), cooccurrences.cols(), config, seed),
+            }
+        }
+    };
+
+    let mut glove = checkpoint.glove;
+    let mut monitor = Monitor::new(config, progress);
+    monitor.resume(checkpoint.last_loss);
+    let mut order = vec![];
+    for epoch in checkpoint.epoch..config.epochs() {
+        shuffle(&mut order, cells.len(), seed, epoch);
+        let loss = glove.epoch(&cells, &order, config.threads);
+        let carry_on = monitor.epoch_done(epoch, loss);
+
+        if let Some(ref path) = config.checkpoint {
+            if !carry_on || (epoch + 1) % config.checkpoint_every.max(1) == 0 {
+                let checkpoint = Checkpoint {
+                    seed: seed,
+                    epoch: epoch + 1,
+                    last_loss: Some(loss),
+                    glove: glove,
+                };
+                checkpoint.save(path).expect("Couldn't save training checkpoint");
+                glove = checkpoint.glove;
+            }
+        }
+        if !carry_on {
+            break;
+        }
+    }
+    glove
+}
+
+#[cfg(test)]
+mod test {
+    use super::{weight, shuffle, train, Checkpoint, Glove};
+    use std::env;
+    use models::config::TrainingConfig;
+    use models::cooccur::Cooccurrences;
+
+    #[test]
+    fn test_weight() {
+        assert_eq!(weight(100.0, 100.0, 0.75), 1.0);
+        assert_eq!(weight(1000.0, 100.0, 0.75), 1.0);
+        assert!((weight(100.0 / 16.0, 100.0, 0.75) - 0.125).abs() < 1e-6);
+    }
+
+    fn counts() -> Vec<(u32, u32, f32)> {
+        let mut cooc = Cooccurrences::new(3, 3);
+        for &(row, col, x) in &[(0, 1, 50.0), (1, 0, 50.0), (1, 2, 5.0), (2, 1, 5.0),
+                                (0, 2, 1.0), (2, 2, 200.0)] {
+            cooc.add(row, col, x);
+        }
+        cooc.nonzero()
+    }
+
+    fn fit(threads: usize) -> Glove {
+        let cells = counts();
+        assert_eq!(cells.len(), 6);
+
+        let config = TrainingConfig { rank: 4, ..TrainingConfig::default() };
+        let glove = Glove::new(3, 3, &config, 0);
+        let mut order = vec![];
+        shuffle(&mut order, cells.len(), 0, 0);
+        let first = glove.epoch(&cells, &order, threads);
+        let mut last = first;
+        for epoch in 1..500 {
+            shuffle(&mut order, cells.len(), 0, epoch);
+            last = glove.epoch(&cells, &order, threads);
+        }
+        assert!(last < first / 10.0);
+        assert!((glove.predict(2, 2) - 200f32.ln()).abs() < 0.5);
+        glove
+    }
+
+    #[test]
+    fn test_fits_counts() {
+        let predictions = |glove: Glove| {
+            (0..9).map(|i| glove.predict(i / 3, i % 3)).collect::<Vec<_>>()
+        };
+        assert_eq!(predictions(fit(1)), predictions(fit(1)));
+        fit(3);
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let (mut order, mut again) = (vec![], vec![]);
+        shuffle(&mut order, 100, 5, 2);
+        shuffle(&mut again, 100, 5, 2);
+        assert_eq!(order, again);
+
+        shuffle(&mut again, 100, 5, 3);
+        assert!(order != again);
+        again.sort();
+        assert_eq!(again, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_resume() {
+        let mut cooc = Cooccurrences::new(3, 3);
+        for &(row, col, x) in &counts() {
+            cooc.add(row as usize, col as usize, x);
+        }
+        let path = env::temp_dir().join("glove_resume.checkpoint");
+        let mut config = TrainingConfig {
+            rank: 4,
+            epochs: Some(6),
+            checkpoint: Some(path.clone()),
+            checkpoint_every: 2,
+            ..TrainingConfig::default()
+        };
+        let predictions = |glove: Glove| {
+            (0..9).map(|i| glove.predict(i / 3, i % 3)).collect::<Vec<_>>()
+        };
+        let whole = predictions(train(&cooc, &config, 7, &mut |_| {}));
+
+        // stopping after three epochs leaves a checkpoint at three, to carry on from
+        config.epochs = Some(3);
+        train(&cooc, &config, 7, &mut |_| {});
+        config.epochs = Some(6);
+        config.resume = true;
+        let mut epochs = vec![];
+        let resumed = train(&cooc, &config, 7, &mut |p| epochs.push(p.epoch));
+        assert_eq!(epochs, vec![3, 4, 5]);
+        assert_eq!(predictions(resumed), whole);
+
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert!(checkpoint.check(&cooc, &config, 7).is_ok());
+        assert!(checkpoint.check(&cooc, &config, 8).is_err());
+        config.learning_rate = Some(config.learning_rate() * 2.0);
+        assert!(checkpoint.check(&cooc, &config, 7).is_err());
+    }
+}
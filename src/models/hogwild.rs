@@ -0,0 +1,121 @@
+use models::linalg::Matrix;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// An `f32` that several threads can read and write at once, stored as its bits.
+///
+/// Loads and stores are relaxed: nothing orders them between threads, so when two threads
+/// update the same value at once one of the updates can be lost, but a read always sees a
+/// value that some thread wrote. That's all Hogwild training asks for.
+pub struct AtomicF32(AtomicU32);
+
+impl AtomicF32 {
+    pub fn new(x: f32) -> AtomicF32 {
+        AtomicF32(AtomicU32::new(x.to_bits()))
+    }
+
+    #[inline]
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    #[inline]
+    pub fn set(&self, x: f32) {
+        self.0.store(x.to_bits(), Ordering::Relaxed)
+    }
+}
+
+/// Copies of `xs` that can be shared between training threads.
+pub fn share(xs: &[f32]) -> Vec<AtomicF32> {
+    xs.iter().map(|&x| AtomicF32::new(x)).collect()
+}
+
+/// The values `xs` hold now.
+pub fn values(xs: &[AtomicF32]) -> Vec<f32> {
+    xs.iter().map(|x| x.get()).collect()
+}
+
+/// A dense matrix that training threads all update at once without any locking, as in
+/// Hogwild. Updates only ever go through a shared reference, so there's never more than
+/// one `&mut` to the parameters, however many threads are training.
+pub struct HogwildMatrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<AtomicF32>,
+}
+
+impl HogwildMatrix {
+    pub fn new(matrix: &Matrix) -> HogwildMatrix {
+        HogwildMatrix {
+            rows: matrix.rows(),
+            cols: matrix.cols(),
+            data: share(matrix.data()),
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Every element, row by row.
+    pub fn data(&self) -> &[AtomicF32] {
+        &self.data
+    }
+
+    #[inline]
+    pub fn row(&self, row: usize) -> &[AtomicF32] {
+        &self.data[row * self.cols..(row + 1) * self.cols]
+    }
+
+    /// The dot product of row `row` with `x`.
+    #[inline]
+    pub fn dot(&self, row: usize, x: &[f32]) -> f32 {
+        self.row(row).iter().zip(x.iter()).fold(0.0, |sum, (a, b)| sum + a.get() * b)
+    }
+
+    /// Adds `alpha` times `x` to row `row`.
+    #[inline]
+    pub fn axpy(&self, row: usize, alpha: f32, x: &[f32]) {
+        for (a, b) in self.row(row).iter().zip(x.iter()) {
+            a.set(a.get() + alpha * b);
+        }
+    }
+
+    /// A copy of the matrix as it is now.
+    pub fn to_matrix(&self) -> Matrix {
+        Matrix::from_vec(self.rows, self.cols, values(&self.data))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HogwildMatrix;
+    use models::linalg::Matrix;
+    use std::thread;
+
+    #[test]
+    fn test_shared_updates() {
+        let matrix = Matrix::from_vec(4, 2, (0..8).map(|x| x as f32).collect());
+        let shared = HogwildMatrix::new(&matrix);
+        assert_eq!(shared.to_matrix(), matrix);
+        assert_eq!(shared.dot(1, &[1.0, 2.0]), 8.0);
+
+        // threads that keep to their own rows lose nothing
+        thread::scope(|scope| {
+            for row in 0..4 {
+                let shared = &shared;
+                scope.spawn(move || {
+                    for _ in 0..100 {
+                        shared.axpy(row, 0.5, &[1.0, -1.0]);
+                    }
+                });
+            }
+        });
+        let expected: Vec<f32> = (0..8).map(|x| x as f32 + if x % 2 == 0 { 50.0 } else { -50.0 })
+                                       .collect();
+        assert_eq!(shared.to_matrix().data(), &expected[..]);
+    }
+}
@@ -1,26 +1,191 @@
-use rand::distributions::{IndependentSample, Range};
+use rand::Rng;
+use rand::distributions::{IndependentSample, Normal, Range};
 
 use std::iter::repeat;
 use std::ops::{IndexMut, Index, Mul, Deref, DerefMut};
 use std::mem;
 
-#[derive(Clone, Debug)]
+/// A dense matrix of `f32`s, stored row by row. Indexing gives a row.
+#[derive(Clone, PartialEq, Debug)]
 pub struct Matrix {
-    height: usize,
+    rows: usize,
+    cols: usize,
     data: Vec<f32>,
 }
 
+/// How the cells of a random matrix are drawn.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Init {
+    /// Uniformly within `scale` either side of zero.
+    Uniform,
+    /// Normally distributed around zero, with standard deviation `scale`.
+    Gaussian,
+    /// Uniformly within `sqrt(6 / (rows + cols))` either side of zero, as suggested by
+    /// Glorot and Bengio. The scale is ignored.
+    Xavier,
+}
+
+impl Init {
+    pub fn from_name(name: &str) -> Option<Init> {
+        match name {
+            "uniform" => Some(Init::Uniform),
+            "gaussian" => Some(Init::Gaussian),
+            "xavier" => Some(Init::Xavier),
+            _ => None,
+        }
+    }
+}
+
 impl Matrix {
-    pub fn random(rows: usize, cols: usize, min: f32, max: f32) -> Matrix {
-        let range = Range::new(min, max);
-        let mut rng = ::rand::weak_rng();
+    /// A matrix with every cell drawn independently from `rng` as `init` says.
+    pub fn random<R: Rng>(rows: usize,
+                          cols: usize,
+                          init: Init,
+                          scale: f32,
+                          rng: &mut R)
+                          -> Matrix {
+        let data = match init {
+            Init::Uniform => sample(Range::new(-scale, scale), rows * cols, rng),
+            Init::Gaussian => {
+                let normal = Normal::new(0.0, scale as f64);
+                (0..rows * cols).map(|_| normal.ind_sample(rng) as f32).collect()
+            }
+            Init::Xavier => {
+                let bound = (6.0 / (rows + cols) as f32).sqrt();
+                sample(Range::new(-bound, bound), rows * cols, rng)
+            }
+        };
+
+        Matrix::from_vec(rows, cols, data)
+    }
+
+    pub fn from_elem(rows: usize, cols: usize, value: f32) -> Matrix {
+        Matrix::from_vec(rows, cols, repeat(value).take(rows * cols).collect())
+    }
+
+    /// A matrix holding `data` row by row.
+    pub fn from_vec(rows: usize, cols: usize, data: Vec<f32>) -> Matrix {
+        assert_eq!(data.len(), rows * cols, "data doesn't fit a {} x {} matrix", rows, cols);
         Matrix {
-            height: cols,
-            data: repeat(range.ind_sample(&mut rng)).take(rows * cols).collect(),
+            rows: rows,
+            cols: cols,
+            data: data,
+        }
+    }
+
+    /// A matrix with the given rows, which must all be the same length as `cols`.
+    pub fn from_rows<R: AsRef<[f32]>>(rows: &[R], cols: usize) -> Matrix {
+        let mut data = Vec::with_capacity(rows.len() * cols);
+        for row in rows {
+            assert_eq!(row.as_ref().len(), cols, "rows must all be the same length");
+            data.extend_from_slice(row.as_ref());
+        }
+        Matrix::from_vec(rows.len(), cols, data)
+    }
+
+    pub fn identity(n: usize) -> Matrix {
+        let mut identity = Matrix::from_elem(n, n, 0.0);
+        for i in 0..n {
+            identity[i][i] = 1.0;
+        }
+        identity
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Every cell, row by row.
+    pub fn data(&self) -> &[f32] {
+        &self.data
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        let mut transpose = Matrix::from_elem(self.cols, self.rows, 0.0);
+        for i in 0..self.rows {
+            for j in 0..self.cols {
+                transpose[j][i] = self[i][j];
+            }
+        }
+        transpose
+    }
+
+    /// The product of this matrix and the column vector `x`.
+    pub fn mul_vec(&self, x: &[f32]) -> Vec<f32> {
+        assert_eq!(x.len(), self.cols);
+        (0..self.rows).map(|i| dot(&self[i], x)).collect()
+    }
+
+    /// The Euclidean length of each row.
+    pub fn row_norms(&self) -> Vec<f32> {
+        (0..self.rows).map(|i| dot(&self[i], &self[i]).sqrt()).collect()
+    }
+
+    /// The cells of column `col`, from top to bottom.
+    pub fn column<'a>(&'a self, col: usize) -> Column<'a> {
+        assert!(col < self.cols);
+        Column {
+            matrix: self,
+            col: col,
+            row: 0,
+        }
+    }
+
+    /// Adds `alpha` times `x`, which must be the same shape, to this matrix.
+    pub fn axpy(&mut self, alpha: f32, x: &Matrix) {
+        assert!(self.rows == x.rows && self.cols == x.cols);
+        axpy(alpha, &x.data, &mut self.data);
+    }
+}
+
+/// An iterator over the cells of one column of a matrix.
+pub struct Column<'a> {
+    matrix: &'a Matrix,
+    col: usize,
+    row: usize,
+}
+
+impl<'a> Iterator for Column<'a> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.row == self.matrix.rows {
+            return None;
+        }
+        self.row += 1;
+        Some(self.matrix.data[(self.row - 1) * self.matrix.cols + self.col])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.matrix.rows - self.row;
+        (left, Some(left))
+    }
+}
+
+impl<'a> Mul for &'a Matrix {
+    type Output = Matrix;
+
+    fn mul(self, other: &Matrix) -> Matrix {
+        assert_eq!(self.cols, other.rows, "can't multiply matrices of mismatched shapes");
+        let mut product = Matrix::from_elem(self.rows, other.cols, 0.0);
+        for i in 0..self.rows {
+            // adding up whole rows of `other` keeps the inner loop running along memory
+            for k in 0..self.cols {
+                axpy(self[i][k], &other[k], &mut product[i]);
+            }
         }
+        product
     }
 }
 
+fn sample<R: Rng>(range: Range<f32>, n: usize, rng: &mut R) -> Vec<f32> {
+    (0..n).map(|_| range.ind_sample(rng)).collect()
+}
+
 pub struct Row([f32]);
 
 impl Deref for Row {
@@ -44,8 +209,8 @@ impl Index<usize> for Matrix {
 
     #[inline]
     fn index(&self, index: usize) -> &Row {
-        let start = index * self.height;
-        let end = start + self.height;
+        let start = index * self.cols;
+        let end = start + self.cols;
         unsafe { mem::transmute(&self.data[start..end]) }
     }
 }
@@ -54,8 +219,8 @@ impl Index<usize> for Matrix {
 impl IndexMut<usize> for Matrix {
     #[inline]
     fn index_mut(&mut self, index: usize) -> &mut Row {
-        let start = index * self.height;
-        let end = start + self.height;
+        let start = index * self.cols;
+        let end = start + self.cols;
         unsafe { mem::transmute(&mut self.data[start..end]) }
     }
 }
@@ -68,6 +233,14 @@ impl<'a> Mul for &'a Row {
     }
 }
 
+impl Row {
+    /// Adds `alpha` times `x` to this row.
+    #[inline]
+    pub fn axpy(&mut self, alpha: f32, x: &[f32]) {
+        axpy(alpha, x, &mut self.0);
+    }
+}
+
 #[inline]
 fn dot(m: &[f32], n: &[f32]) -> f32 {
     m.iter()
@@ -76,18 +249,192 @@ fn dot(m: &[f32], n: &[f32]) -> f32 {
      .fold(0.0, |x, y| x + y)
 }
 
+/// `y += alpha x`.
+#[inline]
+fn axpy(alpha: f32, x: &[f32], y: &mut [f32]) {
+    debug_assert!(x.len() == y.len());
+    for (y, x) in y.iter_mut().zip(x.iter()) {
+        *y += alpha * *x;
+    }
+}
+
+/// The weighted Gram matrix `sum(w x x^T)` of vectors `x` of length `n` with weights `w`,
+/// stored row by row.
+pub fn gram<'a, I>(vectors: I, n: usize) -> Vec<f64>
+    where I: IntoIterator<Item = (f64, &'a [f64])>
+{
+    let mut gram = vec![0.0; n * n];
+    for (weight, x) in vectors {
+        for i in 0..n {
+            let wx = weight * x[i];
+            // the matrix is symmetric, so only the lower triangle needs summing
+            for j in 0..i + 1 {
+                gram[i * n + j] += wx * x[j];
+            }
+        }
+    }
+    for i in 0..n {
+        for j in 0..i {
+            gram[j * n + i] = gram[i * n + j];
+        }
+    }
+    gram
+}
+
+/// Replaces the symmetric positive definite `n` × `n` matrix `a` with the lower triangular
+/// `L` for which `L L^T = a`. Returns false, leaving `a` in a mess, if `a` turns out not to
+/// be positive definite.
+pub fn cholesky(a: &mut [f64], n: usize) -> bool {
+    for j in 0..n {
+        let mut diagonal = a[j * n + j];
+        for k in 0..j {
+            diagonal -= a[j * n + k] * a[j * n + k];
+        }
+        if diagonal <= 0.0 {
+            return false;
+        }
+        let diagonal = diagonal.sqrt();
+        a[j * n + j] = diagonal;
+
+        for i in j + 1..n {
+            let mut x = a[i * n + j];
+            for k in 0..j {
+                x -= a[i * n + k] * a[j * n + k];
+            }
+            a[i * n + j] = x / diagonal;
+        }
+        for i in 0..j {
+            a[i * n + j] = 0.0;
+        }
+    }
+    true
+}
+
+/// Solves `a x = b` for symmetric positive definite `a`, leaving `x` in `b`. Returns false
+/// if `a` isn't positive definite.
+pub fn cholesky_solve(a: &mut [f64], b: &mut [f64]) -> bool {
+    let n = b.len();
+    if !cholesky(a, n) {
+        return false;
+    }
+
+    // L y = b, then L^T x = y
+    for i in 0..n {
+        let mut x = b[i];
+        for k in 0..i {
+            x -= a[i * n + k] * b[k];
+        }
+        b[i] = x / a[i * n + i];
+    }
+    for i in (0..n).rev() {
+        let mut x = b[i];
+        for k in i + 1..n {
+            x -= a[k * n + i] * b[k];
+        }
+        b[i] = x / a[i * n + i];
+    }
+    true
+}
+
 #[cfg(test)]
 mod test {
-    use models::linalg::Matrix;
+    use models::linalg::{Matrix, Init, gram, cholesky, cholesky_solve};
+    use models::random::seeded_rng;
 
     #[test]
     fn test_row_mult() {
-        let mat = Matrix {
-            height: 3,
-            data: vec![1., 2., 3., 4., 5., 6.],
-        };
+        let mat = Matrix::from_vec(2, 3, vec![1., 2., 3., 4., 5., 6.]);
 
         assert_eq!(14., &mat[0] * &mat[0]);
         assert_eq!(18. + 10. + 4., &mat[0] * &mat[1]);
     }
+
+    #[test]
+    fn test_random_init() {
+        for &init in &[Init::Uniform, Init::Gaussian, Init::Xavier] {
+            let mat = Matrix::random(50, 40, init, 0.5, &mut seeded_rng(3, 0, 0));
+            let again = Matrix::random(50, 40, init, 0.5, &mut seeded_rng(3, 0, 0));
+            let other = Matrix::random(50, 40, init, 0.5, &mut seeded_rng(4, 0, 0));
+            assert_eq!(mat.data, again.data);
+            assert!(mat.data != other.data);
+            assert!(mat.data[0] != mat.data[1]);
+
+            let mean = mat.data.iter().fold(0.0, |x, y| x + y) / mat.data.len() as f32;
+            assert!(mean.abs() < 0.05);
+        }
+
+        let uniform = Matrix::random(50, 40, Init::Uniform, 0.5, &mut seeded_rng(3, 0, 0));
+        assert!(uniform.data.iter().all(|x| x.abs() <= 0.5));
+        assert!(uniform.data.iter().any(|x| x.abs() > 0.4));
+
+        let xavier = Matrix::random(50, 40, Init::Xavier, 0.5, &mut seeded_rng(3, 0, 0));
+        assert!(xavier.data.iter().all(|x| x.abs() <= (6.0f32 / 90.0).sqrt()));
+
+        let gaussian = Matrix::random(50, 40, Init::Gaussian, 0.5, &mut seeded_rng(3, 0, 0));
+        let variance = gaussian.data.iter().map(|x| x * x).fold(0.0, |x, y| x + y) / 2000.0;
+        assert!((variance.sqrt() - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_shapes() {
+        let mat = Matrix::from_rows(&[vec![1., 2., 3.], vec![4., 5., 6.]], 3);
+        assert_eq!((mat.rows(), mat.cols()), (2, 3));
+        assert_eq!(mat.data(), &[1., 2., 3., 4., 5., 6.]);
+        assert_eq!(mat, Matrix::from_vec(2, 3, vec![1., 2., 3., 4., 5., 6.]));
+
+        let transpose = mat.transpose();
+        assert_eq!(transpose, Matrix::from_vec(3, 2, vec![1., 4., 2., 5., 3., 6.]));
+        assert_eq!(transpose.transpose(), mat);
+        assert_eq!(mat.column(1).collect::<Vec<_>>(), vec![2., 5.]);
+        assert_eq!(transpose.column(1).collect::<Vec<_>>(), vec![4., 5., 6.]);
+
+        let empty = Matrix::from_elem(3, 0, 0.0);
+        assert_eq!((empty.rows(), empty.cols()), (3, 0));
+        assert_eq!(empty.transpose().rows(), 0);
+    }
+
+    #[test]
+    fn test_products() {
+        let a = Matrix::from_vec(2, 3, vec![1., 2., 3., 4., 5., 6.]);
+        let b = Matrix::from_vec(3, 2, vec![7., 8., 9., 10., 11., 12.]);
+        assert_eq!(&a * &b, Matrix::from_vec(2, 2, vec![58., 64., 139., 154.]));
+        assert_eq!(&b * &a,
+                   Matrix::from_vec(3, 3, vec![39., 54., 69., 49., 68., 87., 59., 82., 105.]));
+        assert_eq!(&a * &Matrix::identity(3), a);
+        assert_eq!(a.mul_vec(&[1., 0., -1.]), vec![-2., -2.]);
+    }
+
+    #[test]
+    fn test_norms_and_axpy() {
+        let mut a = Matrix::from_vec(2, 2, vec![3., 4., 0., -2.]);
+        assert_eq!(a.row_norms(), vec![5., 2.]);
+
+        a.axpy(2.0, &Matrix::identity(2));
+        assert_eq!(a, Matrix::from_vec(2, 2, vec![5., 4., 0., 0.]));
+        a[1].axpy(-1.0, &[1., 2.]);
+        assert_eq!(a[1].to_vec(), vec![-1., -2.]);
+    }
+
+    #[test]
+    fn test_gram() {
+        let (x, y) = ([1.0, 2.0], [3.0, -1.0]);
+        assert_eq!(gram(vec![(1.0, &x[..]), (2.0, &y[..])], 2),
+                   vec![19.0, -4.0, -4.0, 6.0]);
+    }
+
+    #[test]
+    fn test_cholesky() {
+        let mut a = vec![4.0, 12.0, -16.0, 12.0, 37.0, -43.0, -16.0, -43.0, 98.0];
+        assert!(cholesky(&mut a, 3));
+        assert_eq!(a, vec![2.0, 0.0, 0.0, 6.0, 1.0, 0.0, -8.0, 5.0, 3.0]);
+
+        let mut a = vec![4.0, 12.0, -16.0, 12.0, 37.0, -43.0, -16.0, -43.0, 98.0];
+        let mut b = vec![-20.0, -43.0, 192.0];
+        assert!(cholesky_solve(&mut a, &mut b));
+        for (x, y) in b.iter().zip([1.0, 2.0, 3.0].iter()) {
+            assert!((x - y).abs() < 1e-9);
+        }
+
+        assert!(!cholesky(&mut vec![1.0, 2.0, 2.0, 1.0], 2));
+    }
 }
@@ -1,4 +1,21 @@
 mod linalg;
+mod hogwild;
+mod random;
+mod cooccur;
+mod spill;
+mod window;
+mod glove;
+mod als;
+mod svd;
+mod trainer;
+mod word2vec;
+mod subword;
+mod config;
 mod models;
 
-pub use models::models::{WordVec, LanguageModel, LanguageModelBuilder};
+pub use models::models::{WordVec, LanguageModel, LanguageModelBuilder, Vectors};
+pub use models::window::{Boundary, ContextWeighting};
+pub use models::config::{TrainingConfig, Progress, Monitor};
+pub use models::trainer::{is_trainer, TRAINERS};
+pub use models::word2vec::{Architecture, Word2Vec};
+pub use models::linalg::Init;
@@ -1,4 +1,13 @@
+use models::config::{TrainingConfig, Progress};
+use models::cooccur::Cooccurrences;
 use models::linalg::Matrix;
+use models::trainer::{self, Trainer};
+use models::spill::Spill;
+use models::subword::Subwords;
+use models::window::{WindowSettings, Boundary, ContextWeighting};
+use error::Error;
+use mayberef::MaybeRef;
+use mayberef::MaybeRef::{Ref, Val};
 use std::ops::{Add, Sub, Div};
 use std::collections::HashMap;
 use std::iter::FromIterator;
@@ -7,12 +16,18 @@ use std::fmt::{Debug, Formatter};
 use std::fmt;
 use std::cmp::Ordering::Equal;
 use std::ops::Drop;
-use std::cmp;
+use std::collections::VecDeque;
 use std::path::Path;
 use std::io::{BufWriter, Write, BufReader, Read, BufRead};
 use std::io;
 use std::fs::File;
 use std::mem;
+use rand::{Rng, XorShiftRng};
+
+/// Saved builders start with these bytes and then the version of the format they're in, so
+/// that anything else is turned away rather than misread.
+const MAGIC: &'static [u8] = b"wordvec\n";
+const VERSION: u32 = 1;
 
 #[derive(Clone)]
 pub struct WordVec {
@@ -45,31 +60,19 @@ impl WordVec {
             .sqrt()
     }
 
-    fn new(word: String, num_words: usize) -> WordVec {
+    fn new(word: String, dimensions: usize) -> WordVec {
         WordVec {
             word: word,
-            vec: repeat(0.0).take(num_words).collect(),
+            vec: repeat(0.0).take(dimensions).collect(),
         }
     }
 
-    #[allow(dead_code)]
-    fn cooccurences(&self) -> f32 {
-        self.vec.iter().fold(0.0, |x, y| {
-            assert!(!y.is_nan());
-            x + *y
-        })
-    }
-
-    fn normalize(&mut self) {
-        for f in &mut self.vec {
-            *f = (*f + 1.0).log2();
+    fn add_row(&mut self, row: &[f32]) {
+        for (x, y) in self.vec.iter_mut().zip(row.iter()) {
+            *x += *y;
         }
     }
 
-    fn inc(&mut self, i: usize, dist: usize) {
-        assert!(dist != 0);
-        self.vec[i] += 1.0 / dist as f32;
-    }
 }
 
 impl Div<i32> for WordVec {
@@ -125,17 +128,68 @@ pub trait IndexableModel {
     fn size(&self) -> usize;
 }
 
+/// Which of the trained vectors a `LanguageModel` is made from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Vectors {
+    /// The word vectors, W.
+    Words,
+    /// The context vectors, W~. With positional contexts a word's vectors for each offset
+    /// are added together, and words that aren't contexts get a zero vector.
+    Contexts,
+    /// W + W~, which tends to do a little better than either alone.
+    Sum,
+}
+
+impl Vectors {
+    pub fn from_name(name: &str) -> Option<Vectors> {
+        match name {
+            "words" => Some(Vectors::Words),
+            "contexts" => Some(Vectors::Contexts),
+            "sum" => Some(Vectors::Sum),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct LanguageModel {
     words: HashMap<String, usize>,
     word_vecs: Vec<WordVec>,
+    subwords: Option<Subwords>,
 }
 
 pub struct LanguageModelBuilder {
-    window_radius: usize,
+    settings: WindowSettings,
     words: HashMap<String, usize>,
-    word_vecs: Vec<WordVec>,
-    sentence: Vec<Option<usize>>,
+    vocabulary: Vec<String>,
+    contexts: HashMap<String, usize>,
+    context_vocabulary: Vec<String>,
+    word_counts: Vec<u64>,
+    cooccurrences: Cooccurrences,
+    spill: Option<Spill>,
+    /// The number to give the next document. It carries on from the documents counted before
+    /// a builder was saved, so that counting more later doesn't replay their dynamic windows.
+    documents: u64,
+    rng: XorShiftRng,
+    window: VecDeque<Recent>,
+}
+
+/// A word still within range of the next: its index as a target and as a context, and the
+/// right radius it was given.
+#[derive(Clone, Copy)]
+struct Recent {
+    target: Option<usize>,
+    context: Option<usize>,
+    right: usize,
+}
+
+/// Word and co-occurrence counts taken out of a builder with `take_counts`, along with any
+/// of them that were spilled to disk.
+pub struct Counts {
+    word_counts: Vec<u64>,
+    cooccurrences: Cooccurrences,
+    spill: Option<Spill>,
+    documents: u64,
 }
 
 pub struct WordAcceptor<'a> {
@@ -143,34 +197,302 @@ pub struct WordAcceptor<'a> {
 }
 
 impl LanguageModelBuilder {
+    #[cfg(test)]
     pub fn new(window_radius: usize, words: Vec<String>) -> LanguageModelBuilder {
+        let contexts = words.clone();
+        LanguageModelBuilder::with_contexts(window_radius, words, contexts)
+    }
+
+    /// A builder that learns vectors for `targets` from how often they appear near each of
+    /// `contexts`, rather than using one vocabulary for both. The co-occurrence counts then
+    /// grow with the number of targets times the number of contexts.
+    pub fn with_contexts(window_radius: usize,
+                         targets: Vec<String>,
+                         contexts: Vec<String>)
+                         -> LanguageModelBuilder {
+        let settings = WindowSettings {
+            left: window_radius,
+            right: window_radius,
+            scope: Boundary::Sentence,
+            weighting: ContextWeighting::Harmonic,
+            positional: false,
+            seed: 0,
+        };
+        let word_counts = vec![0; targets.len()];
+        let cooccurrences = Cooccurrences::new(targets.len(), contexts.len());
+        LanguageModelBuilder::from_parts(settings, targets, contexts, word_counts, cooccurrences)
+    }
+
+    fn from_parts(settings: WindowSettings,
+                  vocabulary: Vec<String>,
+                  context_vocabulary: Vec<String>,
+                  word_counts: Vec<u64>,
+                  cooccurrences: Cooccurrences)
+                  -> LanguageModelBuilder {
+        LanguageModelBuilder {
+            rng: settings.rng(0),
+            settings: settings,
+            words: index(&vocabulary),
+            vocabulary: vocabulary,
+            contexts: index(&context_vocabulary),
+            context_vocabulary: context_vocabulary,
+            word_counts: word_counts,
+            cooccurrences: cooccurrences,
+            spill: None,
+            documents: 0,
+            window: VecDeque::new(),
+        }
+    }
 
-        let word_vecs = words.iter()
-                             .map(|s| WordVec::new(s.clone(), words.len()))
-                             .collect();
+    pub fn window_scope(mut self, scope: Boundary) -> LanguageModelBuilder {
+        self.settings.scope = scope;
+        self
+    }
 
-        let words = HashMap::from_iter(words.into_iter()
-                                            .enumerate()
-                                            .map(|(a, b)| (b, a)));
+    /// Counts `left` words before each word and `right` words after it as its context,
+    /// rather than the same number either side. Either may be zero.
+    pub fn radii(mut self, left: usize, right: usize) -> LanguageModelBuilder {
+        self.settings.left = left;
+        self.settings.right = right;
+        self.reset_contexts();
+        self
+    }
 
+    /// Tells contexts apart by their offset from the target word, so that a word two to the
+    /// left is a different context from the same word one to the right.
+    pub fn positional(mut self, positional: bool) -> LanguageModelBuilder {
+        self.settings.positional = positional;
+        self.reset_contexts();
+        self
+    }
+
+    // the number of contexts depends on the window settings, so they can only change before
+    // anything has been counted
+    fn reset_contexts(&mut self) {
+        assert!(self.cooccurrences.cells() == 0 && self.spill.is_none(),
+                "window settings must be chosen before counting");
+        assert!(self.settings.contexts_per_word() > 0,
+                "positional contexts need a window of at least one word");
+        self.cooccurrences = Cooccurrences::new(self.vocabulary.len(),
+                                                self.context_vocabulary.len() *
+                                                self.settings.contexts_per_word());
+    }
+
+    pub fn weighting(mut self, weighting: ContextWeighting) -> LanguageModelBuilder {
+        self.settings.weighting = weighting;
+        self
+    }
+
+    /// Seeds the random window sizes used by `ContextWeighting::DynamicWindow` and the
+    /// starting vectors for training.
+    pub fn seed(mut self, seed: u64) -> LanguageModelBuilder {
+        self.settings.seed = seed;
+        self.rng = self.settings.rng(0);
+        self
+    }
+
+    /// Numbers documents from `first` rather than zero, so that builders counting different
+    /// parts of a corpus, to be merged later, draw different dynamic windows.
+    pub fn first_document(mut self, first: u64) -> LanguageModelBuilder {
+        self.documents = first;
+        self
+    }
+
+    /// The number the next document should be given.
+    pub fn documents(&self) -> u64 {
+        self.documents
+    }
+
+    /// Keeps the co-occurrence counts held in memory to roughly `bytes`, spilling the rest
+    /// to sorted chunk files in `dir` that are merged back together by `save` and `build`.
+    pub fn memory_budget(mut self, bytes: usize, dir: &Path) -> LanguageModelBuilder {
+        self.spill = Some(Spill::new(dir, bytes));
+        self
+    }
+
+    /// A builder with the same vocabulary and window settings as this one, but no counts,
+    /// to count part of the corpus on another thread. If this builder has a memory budget,
+    /// the copy has the same one. Counts made by the copy can be added back with
+    /// `add_counts`.
+    pub fn empty_copy(&self) -> LanguageModelBuilder {
         LanguageModelBuilder {
-            window_radius: window_radius,
-            words: words,
-            word_vecs: word_vecs,
-            sentence: vec![],
+            settings: self.settings.clone(),
+            words: self.words.clone(),
+            vocabulary: self.vocabulary.clone(),
+            contexts: self.contexts.clone(),
+            context_vocabulary: self.context_vocabulary.clone(),
+            word_counts: vec![0; self.vocabulary.len()],
+            cooccurrences: Cooccurrences::new(self.cooccurrences.rows(),
+                                              self.cooccurrences.cols()),
+            spill: self.spill.as_ref().map(|s| s.fresh()),
+            documents: self.documents,
+            rng: self.settings.rng(0),
+            window: VecDeque::new(),
+        }
+    }
+
+    /// Removes and returns the counts, leaving none behind. Any spilled chunks go with them,
+    /// and a fresh spill with the same budget takes their place.
+    pub fn take_counts(&mut self) -> Counts {
+        let empty = Cooccurrences::new(self.cooccurrences.rows(), self.cooccurrences.cols());
+        let spill = match self.spill {
+            Some(ref spill) if !spill.is_empty() => Some(spill.fresh()),
+            _ => None,
+        };
+        Counts {
+            word_counts: mem::replace(&mut self.word_counts, vec![0; self.vocabulary.len()]),
+            cooccurrences: mem::replace(&mut self.cooccurrences, empty),
+            spill: match spill {
+                Some(spill) => mem::replace(&mut self.spill, Some(spill)),
+                None => None,
+            },
+            documents: self.documents,
+        }
+    }
+
+    pub fn add_counts(&mut self, counts: &Counts) {
+        self.documents = self.documents.max(counts.documents);
+        for (total, count) in self.word_counts.iter_mut().zip(counts.word_counts.iter()) {
+            *total += *count;
         }
+        match counts.spill {
+            Some(ref spill) => {
+                // read back a row at a time, so the counts never all have to be in memory
+                let mut merge = spill.merge(&counts.cooccurrences)
+                                     .expect("Couldn't read spilled co-occurrence counts");
+                for row in 0..counts.cooccurrences.rows() {
+                    let cells = merge.row(row)
+                                     .expect("Couldn't read spilled co-occurrence counts");
+                    for (col, weight) in cells {
+                        self.cooccurrences.add(row, col, weight);
+                    }
+                    self.check_memory();
+                }
+            }
+            None => {
+                self.cooccurrences.add_all(&counts.cooccurrences);
+                self.check_memory();
+            }
+        }
+    }
+
+    fn check_memory(&mut self) {
+        if let Some(ref mut spill) = self.spill {
+            if spill.is_full(&self.cooccurrences) {
+                spill.spill(&mut self.cooccurrences)
+                     .expect("Couldn't spill co-occurrence counts to disk");
+            }
+        }
+    }
+
+    /// Adds the counts of `other`, built over another part of the corpus, to this builder.
+    /// The two must have the same window settings and the same target and context
+    /// vocabularies, although the words may be in a different order.
+    pub fn merge(&mut self, mut other: LanguageModelBuilder) -> Result<(), Error> {
+        if self.settings != other.settings {
+            return Err(Error::Mismatch(format!("window settings differ, {:?} against {:?}",
+                                               self.settings,
+                                               other.settings)));
+        }
+
+        let remap = try!(remap_words(&other.vocabulary, &self.words, "target"));
+        let remap_contexts = try!(remap_words(&other.context_vocabulary,
+                                              &self.contexts,
+                                              "context"));
+
+        try!(other.unspill());
+        self.documents = self.documents.max(other.documents);
+        let per_word = self.settings.contexts_per_word();
+        for (i, count) in other.word_counts.into_iter().enumerate() {
+            self.word_counts[remap[i]] += count;
+        }
+        for row in 0..other.cooccurrences.rows() {
+            for (col, weight) in other.cooccurrences.row(row) {
+                let context = remap_contexts[col / per_word] * per_word + col % per_word;
+                self.cooccurrences.add(remap[row], context, weight);
+            }
+        }
+        self.check_memory();
+
+        Ok(())
     }
 
-    pub fn build(mut self) -> LanguageModel {
-        for vec in self.word_vecs.iter_mut() {
-            vec.normalize();
+    /// Merges any spilled counts back into memory and stops spilling.
+    fn unspill(&mut self) -> io::Result<()> {
+        if let Some(spill) = self.spill.take() {
+            self.cooccurrences = try!(spill.collect(&self.cooccurrences));
         }
+        Ok(())
+    }
+
+    /// Trains a model on the counts so far with the trainer `config` names. Training is
+    /// seeded with the builder's seed, so the same counts and config always give the same
+    /// model.
+    #[cfg(test)]
+    pub fn build(self, config: &TrainingConfig) -> LanguageModel {
+        self.build_with_progress(config, |_| {})
+    }
+
+    /// Like `build`, but calls `progress` with the loss after each epoch of training.
+    pub fn build_with_progress<F>(self, config: &TrainingConfig, progress: F) -> LanguageModel
+        where F: FnMut(&Progress)
+    {
+        let trainer = trainer::trainer(&config.trainer)
+                          .unwrap_or_else(|| panic!("Unknown trainer '{}'", config.trainer));
+        self.train_with(&*trainer, config, progress)
+    }
 
-        solve(&mut self, 200);
+    /// Trains a model on the counts so far with any trainer, not just the named ones.
+    pub fn train_with<F>(mut self,
+                         trainer: &Trainer,
+                         config: &TrainingConfig,
+                         mut progress: F)
+                         -> LanguageModel
+        where F: FnMut(&Progress)
+    {
+        self.unspill().expect("Couldn't merge spilled co-occurrence counts");
+        trainer.train(&self, config, &mut progress)
+    }
+
+    /// The counts to train on, with a row for each target word and a column for each
+    /// context.
+    pub fn cooccurrences(&self) -> &Cooccurrences {
+        &self.cooccurrences
+    }
+
+    /// The seed trainers should draw their random numbers from.
+    pub fn random_seed(&self) -> u64 {
+        self.settings.seed
+    }
+
+    /// A model from trained vectors: `words` has a row for each target word and `contexts`
+    /// one for each context column. Each word gets its word or context vectors, or both, as
+    /// `config` says.
+    pub fn model(&self,
+                 words: &Matrix,
+                 contexts: &Matrix,
+                 config: &TrainingConfig)
+                 -> LanguageModel {
+        let (rank, vectors) = (config.rank, config.vectors());
+        let per_word = self.settings.contexts_per_word();
+        let mut word_vecs = Vec::with_capacity(self.vocabulary.len());
+        for (i, word) in self.vocabulary.iter().enumerate() {
+            let mut vec = WordVec::new(word.clone(), rank);
+            if vectors != Vectors::Contexts {
+                vec.add_row(&words[i]);
+            }
+            if let (true, Some(&c)) = (vectors != Vectors::Words, self.contexts.get(word)) {
+                for offset in 0..per_word {
+                    vec.add_row(&contexts[c * per_word + offset]);
+                }
+            }
+            word_vecs.push(vec);
+        }
 
         LanguageModel {
-            words: self.words,
-            word_vecs: self.word_vecs,
+            words: self.words.clone(),
+            word_vecs: word_vecs,
+            subwords: None,
         }
     }
 
@@ -178,17 +500,65 @@ impl LanguageModelBuilder {
         WordAcceptor { builder: self }
     }
 
-    pub fn save(&self, path: &Path) -> io::Result<()> {
-        let mut file = BufWriter::new(File::create(path).unwrap());
+    /// Starts the document numbered `document`. Dynamic window sizes are drawn from a
+    /// sequence seeded by the document number, so that they don't depend on which other
+    /// documents the builder has seen. Documents should be numbered on from `documents`.
+    pub fn start_document(&mut self, document: u64) {
+        self.end(Boundary::Document);
+        self.rng = self.settings.rng(document);
+        self.documents = self.documents.max(document + 1);
+    }
+
+    pub fn end_paragraph(&mut self) {
+        self.end(Boundary::Paragraph);
+    }
 
-        try!(write_raw(self.word_vecs.len(), &mut file));
+    pub fn end_document(&mut self) {
+        self.end(Boundary::Document);
+    }
+
+    fn end(&mut self, boundary: Boundary) {
+        if boundary >= self.settings.scope {
+            self.window.clear();
+        }
+    }
+
+    /// Saves the vocabularies and their co-occurrence counts. After the magic bytes and the
+    /// format version come the window settings and the number of documents counted, then the
+    /// context words one to a line, then each row as the target word, the number of stored
+    /// cells and then each cell as a column and weight pair.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(try!(File::create(path)));
+
+        try!(file.write(MAGIC));
+        try!(write_raw(VERSION, &mut file));
+        try!(self.settings.write(&mut file));
+        try!(write_raw(self.documents, &mut file));
+        try!(write_raw(self.vocabulary.len() as u64, &mut file));
+        try!(write_raw(self.context_vocabulary.len() as u64, &mut file));
         try!(file.write(&[b'\n']));
+        for word in &self.context_vocabulary {
+            try!(file.write(word.as_bytes()));
+            try!(file.write(&[b'\n']));
+        }
+
+        let mut merge = match self.spill {
+            Some(ref spill) => Some(try!(spill.merge(&self.cooccurrences))),
+            None => None,
+        };
 
-        for vec in &self.word_vecs {
-            try!(file.write(vec.word.as_bytes()));
+        for (i, word) in self.vocabulary.iter().enumerate() {
+            let cells = match merge {
+                Some(ref mut merge) => try!(merge.row(i)),
+                None => self.cooccurrences.row(i),
+            };
+            try!(file.write(word.as_bytes()));
             try!(file.write(&[b':']));
-            for f in &vec.vec {
-                try!(write_raw(*f, &mut file));
+            try!(write_raw(self.word_counts[i], &mut file));
+            try!(write_raw(cells.len() as u64, &mut file));
+            for (col, weight) in cells {
+                try!(write_raw(col as u32, &mut file));
+                try!(write_raw(weight, &mut file));
             }
             try!(file.write(&[b'\n']));
         }
@@ -196,117 +566,207 @@ impl LanguageModelBuilder {
     }
 
     pub fn load(path: &Path) -> io::Result<LanguageModelBuilder> {
-        let mut file = BufReader::new(File::open(path).unwrap());
-        let mut word_vecs = Vec::new();
+        let mut file = BufReader::new(try!(File::open(path)));
 
+        let mut magic = vec![0; MAGIC.len()];
+        try!(file.read_exact(&mut magic));
+        if magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a saved model"));
+        }
+        let version = unsafe { try!(read_raw::<u32, _>(&mut file)) };
+        if version != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("unknown model format version {}", version)));
+        }
+        let settings = try!(WindowSettings::read(&mut file));
+        let documents: u64 = unsafe { try!(read_raw::<u64, _>(&mut file)) };
         let size: u64 = unsafe { try!(read_raw::<u64, _>(&mut file)) };
+        let num_contexts: u64 = unsafe { try!(read_raw::<u64, _>(&mut file)) };
         try!(read_byte(b'\n', &mut file));
 
-        for _ in 0..size {
+        let mut context_vocabulary = Vec::with_capacity(num_contexts as usize);
+        for _ in 0..num_contexts {
+            let mut word: Vec<u8> = Vec::new();
+            try!(file.read_until(b'\n', &mut word));
+            assert_eq!(Some(b'\n'), word.pop());
+            context_vocabulary.push(String::from_utf8(word).unwrap());
+        }
+
+        let mut vocabulary = Vec::with_capacity(size as usize);
+        let mut word_counts = Vec::with_capacity(size as usize);
+        let mut cooccurrences = Cooccurrences::new(size as usize,
+                                                   num_contexts as usize *
+                                                   settings.contexts_per_word());
+        for row in 0..size as usize {
             let mut word: Vec<u8> = Vec::new();
-            file.read_until(b':', &mut word).unwrap();
+            try!(file.read_until(b':', &mut word));
             assert_eq!(Some(b':'), word.pop());
-            let word = String::from_utf8(word).unwrap();
+            vocabulary.push(String::from_utf8(word).unwrap());
+            word_counts.push(unsafe { try!(read_raw::<u64, _>(&mut file)) });
 
-            let mut vec: Vec<f32> = repeat(0f32).take(size as usize).collect();
-            for f in &mut vec {
+            let cells: u64 = unsafe { try!(read_raw::<u64, _>(&mut file)) };
+            for _ in 0..cells {
                 unsafe {
-                    *f = try!(read_raw::<f32, _>(&mut file));
+                    let col = try!(read_raw::<u32, _>(&mut file));
+                    let weight = try!(read_raw::<f32, _>(&mut file));
+                    cooccurrences.add(row, col as usize, weight);
                 }
             }
 
             try!(read_byte(b'\n', &mut file));
-
-            word_vecs.push(WordVec {
-                word: word,
-                vec: vec,
-            });
         }
 
-        let words: HashMap<_, _> = word_vecs.iter()
-                                            .map(|v| v.word.clone())
-                                            .enumerate()
-                                            .map(|(i, w)| (w, i))
-                                            .collect();
-
-        Ok(LanguageModelBuilder {
-            window_radius: 0,
-            words: words,
-            word_vecs: word_vecs,
-            sentence: Vec::new(),
-        })
+        let builder = LanguageModelBuilder::from_parts(settings,
+                                                       vocabulary,
+                                                       context_vocabulary,
+                                                       word_counts,
+                                                       cooccurrences);
+        Ok(builder.first_document(documents))
     }
 }
 
 impl<'a> WordAcceptor<'a> {
+    /// Counts the co-occurrences of `word` with the words before it that are still inside
+    /// the window: `word` as their right context, and they as its left context.
     pub fn add_word(&mut self, word: &str) {
-        let idx_opt = self.builder.words.get(word).map(|w| *w);
-
-        self.builder.sentence.push(idx_opt);
-    }
-}
-
-impl<'a> Drop for WordAcceptor<'a> {
-    fn drop(&mut self) {
         let LanguageModelBuilder {
-            ref mut sentence,
-            ref mut word_vecs,
-            window_radius,
+            ref settings,
+            ref words,
+            ref contexts,
+            ref mut window,
+            ref mut word_counts,
+            ref mut cooccurrences,
+            ref mut rng,
             ..
         } = *self.builder;
 
-        for (i, &from_word) in sentence.iter().enumerate() {
-            let start = cmp::max(0, i as isize - window_radius as isize) as usize;
-            let end = cmp::min(sentence.len(), i + window_radius + 1);
-            for j in (start..i).chain(i + 1..end) {
-                let dist = cmp::max(i, j) - cmp::min(i, j);
+        let idx_opt = words.get(word).map(|w| *w);
+        let context = contexts.get(word).map(|c| *c);
+        let (mut left, mut right) = (settings.left, settings.right);
+
+        if let Some(t) = idx_opt {
+            word_counts[t] += 1;
+            if settings.weighting == ContextWeighting::DynamicWindow && settings.span() > 0 {
+                let radius = rng.gen_range(1, settings.span() + 1);
+                left = left.min(radius);
+                right = right.min(radius);
+            }
 
-                if let (Some(f), Some(t)) = (from_word, sentence[j]) {
-                    word_vecs[f].inc(t, dist);
+        }
+
+        for (i, recent) in window.iter().rev().enumerate() {
+            let dist = i + 1;
+            if let (Some(f), Some(c)) = (recent.target, context) {
+                if dist <= recent.right {
+                    let weight = settings.weighting.weight(dist, settings.right);
+                    cooccurrences.add(f, settings.right_context(c, dist), weight);
+                }
+            }
+            if let (Some(t), Some(c)) = (idx_opt, recent.context) {
+                if dist <= left {
+                    let weight = settings.weighting.weight(dist, settings.left);
+                    cooccurrences.add(t, settings.left_context(c, dist), weight);
                 }
             }
         }
 
-        sentence.clear();
+        window.push_back(Recent {
+            target: idx_opt,
+            context: context,
+            right: right,
+        });
+        if window.len() > settings.span() {
+            window.pop_front();
+        }
+
+        self.builder.check_memory();
     }
 }
 
-const ALPHA: f32 = 0.0002;
-
-pub fn solve(lmb: &mut LanguageModelBuilder, k: usize) {
-    let mut p = Matrix::random(lmb.word_vecs.len(), k, 0.0, 3.);
-    let mut q = Matrix::random(lmb.word_vecs.len(), k, 0.0, 3.);
-
-    for _ in 0..100 {
-        step(&mut p, &mut q, lmb);
+impl<'a> Drop for WordAcceptor<'a> {
+    fn drop(&mut self) {
+        self.builder.end(Boundary::Sentence);
     }
+}
 
-    let len = lmb.word_vecs.len();
-    for i in 0..len {
-        for j in 0..len {
-            lmb.word_vecs[i].vec[j] = &p[i] * &q[j];
-        }
-    }
+fn index(vocabulary: &[String]) -> HashMap<String, usize> {
+    HashMap::from_iter(vocabulary.iter().cloned().enumerate().map(|(a, b)| (b, a)))
 }
 
-fn step(p: &mut Matrix, q: &mut Matrix, lmb: &LanguageModelBuilder) {
-    let len = lmb.word_vecs.len();
-    for i in 0..len {
-        for j in 0..len {
-            let eij = lmb.word_vecs[i].vec[j] - &p[i] * &q[j];
-            for k in 0..q[i].len() {
-                let delta_p = ALPHA * eij * q[i][k];
-                let delta_q = ALPHA * eij * p[j][k];
-                p[i][k] += delta_p;
-                q[j][k] += delta_q;
+/// Maps each position in `theirs` to where the same word is in `ours`, so long as the two
+/// hold the same words.
+fn remap_words(theirs: &[String],
+               ours: &HashMap<String, usize>,
+               kind: &str)
+               -> Result<Vec<usize>, Error> {
+    let mut remap = Vec::with_capacity(theirs.len());
+    for word in theirs {
+        match ours.get(word) {
+            Some(&i) => remap.push(i),
+            None => {
+                return Err(Error::Mismatch(format!("'{}' is only in one {} vocabulary",
+                                                   word,
+                                                   kind)))
             }
         }
     }
+    if theirs.len() != ours.len() {
+        return Err(Error::Mismatch(format!("{} vocabularies differ in size", kind)));
+    }
+    Ok(remap)
 }
 
 impl LanguageModel {
-    pub fn get(&self, word: &str) -> Option<&WordVec> {
-        self.words.get(word).map(|i| &self.word_vecs[*i])
+    /// A model from vectors trained without a builder, where row `i` of both `words` and
+    /// `contexts` belongs to `vocabulary[i]`. Each word gets its word or context vector, or
+    /// both, as `config` says.
+    pub fn from_vectors(vocabulary: &[String],
+                        words: &Matrix,
+                        contexts: &Matrix,
+                        config: &TrainingConfig)
+                        -> LanguageModel {
+        let word_vecs = vocabulary.iter()
+                                  .enumerate()
+                                  .map(|(i, word)| {
+                                      let mut vec = WordVec::new(word.clone(), config.rank);
+                                      if config.vectors() != Vectors::Contexts {
+                                          vec.add_row(&words[i]);
+                                      }
+                                      if config.vectors() != Vectors::Words {
+                                          vec.add_row(&contexts[i]);
+                                      }
+                                      vec
+                                  })
+                                  .collect();
+
+        LanguageModel {
+            words: index(vocabulary),
+            word_vecs: word_vecs,
+            subwords: None,
+        }
+    }
+
+    /// Makes vectors for words outside the vocabulary from their character n-grams, which
+    /// only compare with the rest if the model holds word vectors alone.
+    pub fn with_subwords(mut self, subwords: Subwords) -> LanguageModel {
+        self.subwords = Some(subwords);
+        self
+    }
+
+    /// The vector for `word`. With subwords, a word outside the vocabulary gets the mean of
+    /// its n-gram vectors; otherwise only words in the vocabulary have vectors.
+    pub fn get<'a>(&'a self, word: &str) -> Option<MaybeRef<'a, WordVec>> {
+        match self.words.get(word) {
+            Some(&i) => Some(Ref(&self.word_vecs[i])),
+            None => {
+                self.subwords.as_ref().and_then(|s| s.compose(word)).map(|vec| {
+                    Val(WordVec {
+                        word: word.to_string(),
+                        vec: vec,
+                    })
+                })
+            }
+        }
     }
 
     pub fn nearest_words(&self, word: &WordVec) -> Vec<&WordVec> {
@@ -331,14 +791,14 @@ impl LanguageModel {
     }
 }
 
-fn read_byte<R: Read>(b: u8, read: &mut R) -> io::Result<()> {
+pub fn read_byte<R: Read>(b: u8, read: &mut R) -> io::Result<()> {
     let buf = &mut [0];
     while try!(read.read(buf)) == 0 {}
     assert_eq!(&[b], buf);
     Ok(())
 }
 
-unsafe fn read_raw<T: Copy, R: Read>(reader: &mut BufReader<R>) -> io::Result<T> {
+pub unsafe fn read_raw<T: Copy, R: Read>(reader: &mut BufReader<R>) -> io::Result<T> {
     let mut buffer = [0u8; 64];
     let t_size = mem::size_of::<T>();
     assert!(t_size <= buffer.len());
@@ -346,6 +806,9 @@ unsafe fn read_raw<T: Copy, R: Read>(reader: &mut BufReader<R>) -> io::Result<T>
     let mut remainder = t_size;
     while remainder > 0 {
         let bytes_read = try!(reader.read(&mut buffer[(t_size - remainder)..t_size]));
+        if bytes_read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected end of file"));
+        }
         remainder -= bytes_read;
     }
 
@@ -353,7 +816,7 @@ unsafe fn read_raw<T: Copy, R: Read>(reader: &mut BufReader<R>) -> io::Result<T>
     Ok(*bptr)
 }
 
-fn write_raw<T: Copy, F: Write>(t: T, writer: &mut F) -> io::Result<usize> {
+pub fn write_raw<T: Copy, F: Write>(t: T, writer: &mut F) -> io::Result<usize> {
     let buffer = [0u8; 64];
     let t_size = mem::size_of::<T>();
     assert!(t_size <= buffer.len());
@@ -371,7 +834,13 @@ fn write_raw<T: Copy, F: Write>(t: T, writer: &mut F) -> io::Result<usize> {
 
 #[cfg(test)]
 mod test {
-    use super::LanguageModelBuilder;
+    use super::{LanguageModel, LanguageModelBuilder, Boundary, ContextWeighting, Vectors, MAGIC};
+    use models::config::{TrainingConfig, Progress};
+    use models::linalg::Matrix;
+    use models::trainer::Trainer;
+    use models::spill::CELL_BYTES;
+    use std::env;
+    use std::fs;
     use std::path::Path;
 
     fn get_builder() -> LanguageModelBuilder {
@@ -392,14 +861,87 @@ mod test {
 
     #[test]
     fn test_accept_sentences() {
-        let model = get_builder().build();
+        // counts of one are already fitted by vectors near zero, so there must be more
+        let mut builder = get_builder();
+        for _ in 0..50 {
+            add_sentence(&mut builder, "x foo bar baz x x x x x x x blort");
+        }
+        let model = builder.build(&TrainingConfig::default());
         let foo = model.get("foo").unwrap();
         let baz = model.get("baz").unwrap();
         let bar = model.get("bar").unwrap();
         let blort = model.get("blort").unwrap();
 
-        assert!(foo.distance(baz) < foo.distance(blort));
-        assert!(foo.distance(bar) == bar.distance(baz));
+        // foo and baz only ever appear next to bar, so they end up alike, and as far from it
+        // as each other give or take the random starting vectors
+        assert!(foo.distance(&baz) < foo.distance(&blort));
+        assert!(foo.distance(&baz) < foo.distance(&bar));
+        assert!((foo.distance(&bar) - bar.distance(&baz)).abs() < 0.05 * foo.distance(&bar));
+    }
+
+    #[test]
+    fn test_vector_dimensions() {
+        for &vectors in &[Vectors::Words, Vectors::Contexts, Vectors::Sum] {
+            let config = TrainingConfig { vectors: Some(vectors), ..TrainingConfig::default() };
+            let model = get_builder().build(&config);
+            assert_eq!(model.get("foo").unwrap().vec.len(), 200);
+        }
+
+        let words = |list: &str| list.split(" ").map(|w| w.to_string()).collect::<Vec<_>>();
+        let mut builder = LanguageModelBuilder::with_contexts(1, words("a b"), words("a"));
+        add_sentence(&mut builder, "a b a");
+        let model = builder.build(&TrainingConfig {
+            vectors: Some(Vectors::Contexts),
+            ..TrainingConfig::default()
+        });
+        assert!(model.get("b").unwrap().vec.iter().all(|x| *x == 0.0));
+        assert!(model.get("a").unwrap().vec.iter().any(|x| *x != 0.0));
+    }
+
+    #[test]
+    fn test_seeded_training() {
+        let config = TrainingConfig { epochs: Some(5), ..TrainingConfig::default() };
+        let model = get_builder().seed(1).build(&config);
+        assert_eq!(model, get_builder().seed(1).build(&config));
+        assert!(model != get_builder().seed(2).build(&config));
+    }
+
+    #[test]
+    fn test_svd_training() {
+        let config = TrainingConfig {
+            trainer: "svd".to_string(),
+            rank: 2,
+            ..TrainingConfig::default()
+        };
+        let model = get_builder().build(&config);
+        assert_eq!(model, get_builder().build(&config));
+        assert_eq!(model.get("foo").unwrap().vec.len(), 2);
+    }
+
+    struct ConstantTrainer;
+
+    impl Trainer for ConstantTrainer {
+        fn train(&self,
+                 builder: &LanguageModelBuilder,
+                 config: &TrainingConfig,
+                 _: &mut FnMut(&Progress))
+                 -> LanguageModel {
+            let counts = builder.cooccurrences();
+            let words = Matrix::from_elem(counts.rows(), config.rank, 1.0);
+            let contexts = Matrix::from_elem(counts.cols(), config.rank, 2.0);
+            builder.model(&words, &contexts, config)
+        }
+    }
+
+    #[test]
+    fn test_custom_trainer() {
+        let config = TrainingConfig { rank: 3, ..TrainingConfig::default() };
+        let model = get_builder().train_with(&ConstantTrainer, &config, |_| {});
+        assert_eq!(model.get("bar").unwrap().vec, vec![3.0, 3.0, 3.0]);
+
+        let config = TrainingConfig { vectors: Some(Vectors::Words), ..config };
+        let model = get_builder().train_with(&ConstantTrainer, &config, |_| {});
+        assert_eq!(model.get("bar").unwrap().vec, vec![1.0, 1.0, 1.0]);
     }
 
     #[test]
@@ -408,7 +950,245 @@ mod test {
         let path = Path::new("/tmp/model.data");
         builder.save(&path).unwrap();
 
-        let loaded_model = LanguageModelBuilder::load(path).unwrap().build();
-        assert_eq!(builder.build(), loaded_model);
+        let config = TrainingConfig::default();
+        let loaded_model = LanguageModelBuilder::load(path).unwrap().build(&config);
+        assert_eq!(builder.build(&config), loaded_model);
+    }
+
+    #[test]
+    fn test_sparse_serialization() {
+        let builder = get_builder();
+        let path = Path::new("/tmp/sparse_model.data");
+        builder.save(&path).unwrap();
+
+        let loaded = LanguageModelBuilder::load(path).unwrap();
+        assert_eq!(builder.settings, loaded.settings);
+        assert_eq!(builder.vocabulary, loaded.vocabulary);
+        assert_eq!(builder.word_counts, loaded.word_counts);
+        assert_eq!(builder.cooccurrences, loaded.cooccurrences);
+    }
+
+    #[test]
+    fn test_load_rejects_other_files() {
+        let path = Path::new("/tmp/versioned_model.data");
+        get_builder().save(&path).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        assert!(LanguageModelBuilder::load(&path).is_ok());
+
+        // a later version of the format, then something that isn't a model at all
+        bytes[MAGIC.len()] += 1;
+        fs::write(&path, &bytes).unwrap();
+        assert!(LanguageModelBuilder::load(&path).is_err());
+        fs::write(&path, b"foo bar baz\n").unwrap();
+        assert!(LanguageModelBuilder::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_continue_loaded_builder() {
+        let words = "foo bar baz".split(" ").map(|w| w.to_string()).collect::<Vec<_>>();
+        let mut whole = LanguageModelBuilder::new(3, words.clone())
+                            .window_scope(Boundary::Paragraph);
+        add_sentence(&mut whole, "foo x bar baz");
+        whole.end_document();
+        add_sentence(&mut whole, "baz foo foo");
+
+        let mut first = LanguageModelBuilder::new(3, words).window_scope(Boundary::Paragraph);
+        add_sentence(&mut first, "foo x bar baz");
+        let path = Path::new("/tmp/incremental_model.data");
+        first.save(&path).unwrap();
+
+        let mut loaded = LanguageModelBuilder::load(&path).unwrap();
+        add_sentence(&mut loaded, "baz foo foo");
+        assert_eq!(loaded.settings, whole.settings);
+        assert_eq!(loaded.word_counts, vec![3, 1, 2]);
+        assert_eq!(loaded.word_counts, whole.word_counts);
+        assert_eq!(loaded.cooccurrences, whole.cooccurrences);
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let words = "foo bar baz blort".split(" ").map(|w| w.to_string()).collect::<Vec<_>>();
+        let mut expected = LanguageModelBuilder::new(2, words.clone());
+        add_sentence(&mut expected, "x foo bar baz x blort foo x baz bar blort");
+        add_sentence(&mut expected, "blort foo x x bar");
+
+        let mut builder = LanguageModelBuilder::new(2, words)
+                              .memory_budget(3 * CELL_BYTES, &env::temp_dir());
+        add_sentence(&mut builder, "x foo bar baz x blort foo x baz bar blort");
+        add_sentence(&mut builder, "blort foo x x bar");
+        assert!(builder.spill.as_ref().map(|s| s.is_full(&builder.cooccurrences)) == Some(false));
+        // most of the counts can only be on disk
+        assert!(builder.cooccurrences.cells() <= 3);
+        assert!(expected.cooccurrences.cells() > 3);
+
+        let path = Path::new("/tmp/spilled_model.data");
+        builder.save(&path).unwrap();
+        let loaded = LanguageModelBuilder::load(path).unwrap();
+        assert_eq!(loaded.cooccurrences, expected.cooccurrences);
+
+        builder.unspill().unwrap();
+        assert!(builder.spill.is_none());
+        assert_eq!(builder.cooccurrences, expected.cooccurrences);
+    }
+
+    #[test]
+    fn test_merge() {
+        let words = |list: &str| list.split(" ").map(|w| w.to_string()).collect::<Vec<_>>();
+        let mut whole = LanguageModelBuilder::new(2, words("a b c"));
+        add_sentence(&mut whole, "a b x c a");
+        add_sentence(&mut whole, "c c b a");
+
+        let mut first = LanguageModelBuilder::new(2, words("a b c"));
+        add_sentence(&mut first, "a b x c a");
+        let mut second = LanguageModelBuilder::new(2, words("c a b"));
+        add_sentence(&mut second, "c c b a");
+
+        first.merge(second).unwrap();
+        assert_eq!(first.cooccurrences, whole.cooccurrences);
+
+        let wider = LanguageModelBuilder::new(3, words("a b c"));
+        assert!(first.merge(wider).is_err());
+        let different = LanguageModelBuilder::new(2, words("a b d"));
+        assert!(first.merge(different).is_err());
+    }
+
+    fn add_sentence(builder: &mut LanguageModelBuilder, sentence: &str) {
+        let mut acc = builder.new_sentence();
+        for word in sentence.split(' ') {
+            acc.add_word(word);
+        }
+    }
+
+    #[test]
+    fn test_window_scope() {
+        let words = vec!["a".to_string(), "b".to_string()];
+        for &(scope, within, across) in &[(Boundary::Sentence, 0.0, 0.0),
+                                          (Boundary::Paragraph, 1.0, 0.0),
+                                          (Boundary::Document, 1.0, 1.0)] {
+            let mut builder = LanguageModelBuilder::new(1, words.clone()).window_scope(scope);
+            add_sentence(&mut builder, "a x b");
+            builder.end_document();
+            assert_eq!(builder.cooccurrences.get(0, 1), 0.0);
+
+            add_sentence(&mut builder, "a");
+            add_sentence(&mut builder, "b");
+            assert_eq!(builder.cooccurrences.get(0, 1), within);
+
+            builder.end_paragraph();
+            add_sentence(&mut builder, "a");
+            builder.end_document();
+            add_sentence(&mut builder, "b");
+            assert_eq!(builder.cooccurrences.get(1, 0), within + across);
+        }
+    }
+
+    #[test]
+    fn test_dynamic_window() {
+        let words = vec!["a".to_string(), "b".to_string()];
+        let count = |seed| {
+            let mut builder = LanguageModelBuilder::new(4, words.clone())
+                                  .weighting(ContextWeighting::DynamicWindow)
+                                  .seed(seed);
+            for document in 0..20 {
+                builder.start_document(document);
+                add_sentence(&mut builder, "a x x b a x b");
+                builder.end_document();
+            }
+            builder.cooccurrences.clone()
+        };
+
+        let counts = count(7);
+        assert_eq!(counts, count(7));
+        // neighbouring words always see each other, the pairs further apart only sometimes
+        assert!(counts.get(0, 1) > 20.0 && counts.get(0, 1) < 60.0);
+    }
+
+    #[test]
+    fn test_loaded_builder_numbers_documents_on() {
+        let words = vec!["a".to_string(), "b".to_string()];
+        let new_builder = || {
+            LanguageModelBuilder::new(4, words.clone())
+                .weighting(ContextWeighting::DynamicWindow)
+                .seed(3)
+        };
+        let mut whole = new_builder();
+        for document in 0..20 {
+            whole.start_document(document);
+            add_sentence(&mut whole, "a x x b a x b");
+        }
+
+        let mut first = new_builder();
+        for document in 0..10 {
+            first.start_document(document);
+            add_sentence(&mut first, "a x x b a x b");
+        }
+        let path = env::temp_dir().join("numbered_documents.data");
+        first.save(&path).unwrap();
+
+        let mut loaded = LanguageModelBuilder::load(&path).unwrap();
+        assert_eq!(loaded.documents(), 10);
+        for document in 0..10 {
+            let number = loaded.documents();
+            loaded.start_document(number);
+            add_sentence(&mut loaded, "a x x b a x b");
+            assert_eq!(loaded.documents(), 11 + document);
+        }
+        assert_eq!(loaded.cooccurrences, whole.cooccurrences);
+    }
+
+    #[test]
+    fn test_asymmetric_window() {
+        let words = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let mut builder = LanguageModelBuilder::new(0, words.clone())
+                              .radii(2, 0)
+                              .weighting(ContextWeighting::Uniform);
+        add_sentence(&mut builder, "a b c");
+        assert_eq!(builder.cooccurrences.row(0), vec![]);
+        assert_eq!(builder.cooccurrences.row(1), vec![(0, 1.0)]);
+        assert_eq!(builder.cooccurrences.row(2), vec![(0, 1.0), (1, 1.0)]);
+
+        let mut builder = LanguageModelBuilder::new(0, words)
+                              .radii(1, 2)
+                              .positional(true);
+        add_sentence(&mut builder, "a b c");
+        // contexts are at offsets -1, 1 and 2
+        assert_eq!(builder.cooccurrences.cols(), 9);
+        assert_eq!(builder.cooccurrences.row(0), vec![(4, 1.0), (8, 0.5)]);
+        assert_eq!(builder.cooccurrences.row(1), vec![(0, 1.0), (7, 1.0)]);
+        assert_eq!(builder.cooccurrences.row(2), vec![(3, 1.0)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "window of at least one word")]
+    fn test_positional_needs_a_window() {
+        LanguageModelBuilder::new(0, vec!["a".to_string()]).positional(true);
+    }
+
+    #[test]
+    fn test_separate_contexts() {
+        let words = |list: &str| list.split(" ").map(|w| w.to_string()).collect::<Vec<_>>();
+        let mut builder = LanguageModelBuilder::with_contexts(1, words("a b c"), words("x a"))
+                              .weighting(ContextWeighting::Uniform);
+        add_sentence(&mut builder, "a x b a c");
+        assert_eq!(builder.cooccurrences.cols(), 2);
+        assert_eq!(builder.cooccurrences.row(0), vec![(0, 1.0)]);
+        assert_eq!(builder.cooccurrences.row(1), vec![(0, 1.0), (1, 1.0)]);
+        assert_eq!(builder.cooccurrences.row(2), vec![(1, 1.0)]);
+
+        let path = env::temp_dir().join("separate_contexts.data");
+        builder.save(&path).unwrap();
+        let loaded = LanguageModelBuilder::load(&path).unwrap();
+        assert_eq!(builder.context_vocabulary, loaded.context_vocabulary);
+        assert_eq!(builder.cooccurrences, loaded.cooccurrences);
+
+        let mut reordered = LanguageModelBuilder::with_contexts(1, words("a b c"), words("a x"))
+                                .weighting(ContextWeighting::Uniform);
+        add_sentence(&mut reordered, "a x b a c");
+        builder.merge(reordered).unwrap();
+        assert_eq!(builder.cooccurrences.row(1), vec![(0, 2.0), (1, 2.0)]);
+
+        let fewer = LanguageModelBuilder::with_contexts(1, words("a b c"), words("x"))
+                        .weighting(ContextWeighting::Uniform);
+        assert!(builder.merge(fewer).is_err());
     }
 }
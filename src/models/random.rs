@@ -0,0 +1,23 @@
+use rand::{SeedableRng, XorShiftRng};
+
+/// Random numbers for context window sizes, one stream per document.
+pub const WINDOW_STREAM: u32 = 0;
+/// Random numbers for the starting values of trained vectors.
+pub const INIT_STREAM: u32 = 1;
+/// Random numbers for the order cells are visited in while training, one stream per epoch.
+pub const SHUFFLE_STREAM: u32 = 2;
+/// Random numbers for the projection in a randomized SVD.
+pub const SVD_STREAM: u32 = 3;
+/// Random numbers for word2vec's window sizes, subsampling and negative samples, one stream
+/// per document per epoch.
+pub const WORD2VEC_STREAM: u32 = 4;
+
+/// A generator for one of several independent streams of random numbers that all follow
+/// from a single `seed`, so that a model can be reproduced from its seed alone.
+pub fn seeded_rng(seed: u64, stream: u32, index: u64) -> XorShiftRng {
+    // xorshift can't be seeded with all zeros, hence the constant in the last word
+    XorShiftRng::from_seed([seed as u32,
+                            (seed >> 32) as u32 ^ stream.wrapping_mul(0x85ebca6b),
+                            index as u32,
+                            (index >> 32) as u32 ^ 0x9e3779b9])
+}
@@ -0,0 +1,247 @@
+use models::cooccur::Cooccurrences;
+use models::models::{read_raw, write_raw};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+/// Roughly what one stored cell costs in a `Cooccurrences` row, including hash table overhead.
+pub const CELL_BYTES: usize = 16;
+
+static NEXT_SPILL: AtomicUsize = AtomicUsize::new(0);
+
+/// Keeps co-occurrence counts within a memory budget by writing them out to sorted chunk
+/// files whenever the budget is exceeded, in the manner of GloVe's `cooccur` tool. The
+/// chunks are merged back together, in the order they were written, when the counts are
+/// needed. Chunk files are removed when the `Spill` is dropped.
+pub struct Spill {
+    dir: PathBuf,
+    prefix: String,
+    max_cells: usize,
+    chunks: Vec<PathBuf>,
+}
+
+impl Spill {
+    pub fn new(dir: &Path, budget_bytes: usize) -> Spill {
+        Spill {
+            dir: dir.to_path_buf(),
+            prefix: format!("wordvec-{}-{}",
+                            process::id(),
+                            NEXT_SPILL.fetch_add(1, AtomicOrdering::SeqCst)),
+            max_cells: (budget_bytes / CELL_BYTES).max(1),
+            chunks: vec![],
+        }
+    }
+
+    /// A new spill with no chunks, in the same directory as this one and with the same
+    /// budget.
+    pub fn fresh(&self) -> Spill {
+        Spill::new(&self.dir, self.max_cells * CELL_BYTES)
+    }
+
+    /// Whether nothing has been spilled yet.
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn is_full(&self, cooccurrences: &Cooccurrences) -> bool {
+        cooccurrences.cells() > self.max_cells
+    }
+
+    /// Writes every stored cell to a new chunk file as sorted (row, column, weight)
+    /// triples, then empties `cooccurrences`.
+    pub fn spill(&mut self, cooccurrences: &mut Cooccurrences) -> io::Result<()> {
+        let path = self.dir.join(format!("{}-{}.cooc", self.prefix, self.chunks.len()));
+        {
+            let mut file = BufWriter::new(try!(File::create(&path)));
+            for row in 0..cooccurrences.rows() {
+                for (col, weight) in cooccurrences.row(row) {
+                    try!(write_raw(row as u32, &mut file));
+                    try!(write_raw(col as u32, &mut file));
+                    try!(write_raw(weight, &mut file));
+                }
+            }
+            try!(file.flush());
+        }
+
+        self.chunks.push(path);
+        cooccurrences.clear();
+        Ok(())
+    }
+
+    /// Merges the spilled chunks with the cells still held in memory into a single sorted
+    /// stream. Weights for the same cell are added up in the order the chunks were written,
+    /// with the in-memory cells last.
+    pub fn merge(&self, in_memory: &Cooccurrences) -> io::Result<Merge> {
+        let mut sources = vec![];
+        for path in &self.chunks {
+            sources.push(Source::Chunk(BufReader::new(try!(File::open(path)))));
+        }
+
+        let mut cells = vec![];
+        for row in (0..in_memory.rows()).rev() {
+            for (col, weight) in in_memory.row(row).into_iter().rev() {
+                cells.push((row as u32, col as u32, weight));
+            }
+        }
+        sources.push(Source::Memory(cells));
+
+        let mut merge = Merge {
+            sources: sources,
+            heap: BinaryHeap::new(),
+        };
+        for i in 0..merge.sources.len() {
+            try!(merge.refill(i));
+        }
+        Ok(merge)
+    }
+
+    /// Merges everything back into one in-memory `Cooccurrences`.
+    pub fn collect(&self, in_memory: &Cooccurrences) -> io::Result<Cooccurrences> {
+        let mut merged = Cooccurrences::new(in_memory.rows(), in_memory.cols());
+        let mut merge = try!(self.merge(in_memory));
+        for row in 0..in_memory.rows() {
+            for (col, weight) in try!(merge.row(row)) {
+                merged.add(row, col, weight);
+            }
+        }
+        Ok(merged)
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        for path in &self.chunks {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+enum Source {
+    Chunk(BufReader<File>),
+    // held in reverse so that the next cell can be popped off the end
+    Memory(Vec<(u32, u32, f32)>),
+}
+
+impl Source {
+    fn next(&mut self) -> io::Result<Option<(u32, u32, f32)>> {
+        match *self {
+            Source::Memory(ref mut cells) => Ok(cells.pop()),
+            Source::Chunk(ref mut file) => {
+                let row = match unsafe { read_raw::<u32, _>(file) } {
+                    Ok(row) => row,
+                    Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                    Err(e) => return Err(e),
+                };
+                let col = unsafe { try!(read_raw::<u32, _>(file)) };
+                let weight = unsafe { try!(read_raw::<f32, _>(file)) };
+                Ok(Some((row, col, weight)))
+            }
+        }
+    }
+}
+
+struct Head {
+    row: u32,
+    col: u32,
+    source: usize,
+    weight: f32,
+}
+
+impl Head {
+    fn key(&self) -> (u32, u32, usize) {
+        (self.row, self.col, self.source)
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Head) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Head {}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Head) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Head {
+    // reversed, so that the max-heap hands out the smallest cell first
+    fn cmp(&self, other: &Head) -> Ordering {
+        other.key().cmp(&self.key())
+    }
+}
+
+/// A k-way merge over sorted co-occurrence chunks.
+pub struct Merge {
+    sources: Vec<Source>,
+    heap: BinaryHeap<Head>,
+}
+
+impl Merge {
+    fn refill(&mut self, source: usize) -> io::Result<()> {
+        if let Some((row, col, weight)) = try!(self.sources[source].next()) {
+            self.heap.push(Head {
+                row: row,
+                col: col,
+                source: source,
+                weight: weight,
+            });
+        }
+        Ok(())
+    }
+
+    /// The merged cells of `row`, ordered by column. Rows must be requested in order.
+    pub fn row(&mut self, row: usize) -> io::Result<Vec<(usize, f32)>> {
+        let mut cells: Vec<(usize, f32)> = vec![];
+        while self.heap.peek().map(|h| h.row as usize == row).unwrap_or(false) {
+            let head = self.heap.pop().unwrap();
+            try!(self.refill(head.source));
+
+            let col = head.col as usize;
+            match cells.last_mut() {
+                Some(&mut (last, ref mut weight)) if last == col => *weight += head.weight,
+                _ => cells.push((col, head.weight)),
+            }
+        }
+        Ok(cells)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Spill, CELL_BYTES};
+    use models::cooccur::Cooccurrences;
+    use std::env;
+
+    #[test]
+    fn test_spill_and_merge() {
+        let mut spill = Spill::new(&env::temp_dir(), 2 * CELL_BYTES);
+        let mut expected = Cooccurrences::new(3, 3);
+        let mut cooc = Cooccurrences::new(3, 3);
+
+        let cells = [(0, 1, 1.0), (2, 2, 0.5), (0, 1, 0.25), (1, 0, 2.0), (2, 2, 1.0),
+                     (0, 0, 0.125), (0, 1, 4.0)];
+        for &(row, col, weight) in &cells {
+            expected.add(row, col, weight);
+            cooc.add(row, col, weight);
+            if spill.is_full(&cooc) {
+                spill.spill(&mut cooc).unwrap();
+            }
+        }
+
+        assert!(spill.chunks.len() > 1);
+        assert!(cooc.cells() <= 2);
+        assert_eq!(spill.collect(&cooc).unwrap(), expected);
+
+        let paths = spill.chunks.clone();
+        drop(spill);
+        assert!(paths.iter().all(|p| !p.exists()));
+    }
+}
@@ -0,0 +1,115 @@
+use models::config::TrainingConfig;
+use models::linalg::Matrix;
+use rand::Rng;
+
+/// Vectors for the character n-grams of words, as in fastText, so that a vector can be
+/// made for any word from the pieces it shares with words that were seen in training.
+///
+/// There are far too many n-grams to give each its own vector, so they're hashed into a
+/// fixed number of buckets, and n-grams that collide share a vector.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Subwords {
+    min_n: usize,
+    max_n: usize,
+    buckets: usize,
+    rank: usize,
+    pub vectors: Matrix,
+}
+
+impl Subwords {
+    pub fn new<R: Rng>(config: &TrainingConfig, rng: &mut R) -> Subwords {
+        let scale = config.init_scale / config.rank as f32;
+        Subwords {
+            min_n: config.min_n,
+            max_n: config.max_n,
+            buckets: config.buckets,
+            rank: config.rank,
+            vectors: Matrix::random(config.buckets, config.rank, config.init, scale, rng),
+        }
+    }
+
+    /// The same n-grams with the given vectors, one for each bucket.
+    pub fn with_vectors(&self, vectors: Matrix) -> Subwords {
+        assert_eq!((vectors.rows(), vectors.cols()), (self.buckets, self.rank));
+        Subwords { vectors: vectors, ..self.clone() }
+    }
+
+    /// The buckets of every n-gram of `word` from `min_n` to `max_n` characters long, with
+    /// '<' and '>' added to mark the start and end of the word, so that "<un" as a prefix
+    /// is told apart from "un" in the middle.
+    pub fn ngrams(&self, word: &str) -> Vec<usize> {
+        let chars: Vec<char> = Some('<').into_iter().chain(word.chars()).chain(Some('>')).collect();
+        let mut ngrams = vec![];
+        for start in 0..chars.len() {
+            for n in self.min_n..self.max_n.min(chars.len() - start) + 1 {
+                let ngram: String = chars[start..start + n].iter().cloned().collect();
+                ngrams.push(hash(&ngram) as usize % self.buckets);
+            }
+        }
+        ngrams
+    }
+
+    /// A vector for `word` as the mean of its n-gram vectors, or `None` if it's too short
+    /// to have any n-grams.
+    pub fn compose(&self, word: &str) -> Option<Vec<f32>> {
+        let ngrams = self.ngrams(word);
+        if ngrams.is_empty() {
+            return None;
+        }
+
+        let mut vec = vec![0.0; self.rank];
+        for &ngram in &ngrams {
+            for (x, y) in vec.iter_mut().zip(self.vectors[ngram].iter()) {
+                *x += *y / ngrams.len() as f32;
+            }
+        }
+        Some(vec)
+    }
+}
+
+/// The 32 bit FNV-1a hash of the UTF-8 bytes of `s`, as fastText computes it: each byte is
+/// sign-extended first, which only matters for words that aren't ASCII.
+fn hash(s: &str) -> u32 {
+    s.bytes().fold(2166136261, |h, b| (h ^ b as i8 as u32).wrapping_mul(16777619))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Subwords, hash};
+    use models::config::TrainingConfig;
+    use models::random::seeded_rng;
+
+    fn subwords(buckets: usize) -> Subwords {
+        let config = TrainingConfig {
+            rank: 4,
+            min_n: 2,
+            max_n: 3,
+            buckets: buckets,
+            ..TrainingConfig::default()
+        };
+        Subwords::new(&config, &mut seeded_rng(1, 0, 0))
+    }
+
+    #[test]
+    fn test_ngrams() {
+        let subwords = subwords(1 << 20);
+        let expected: Vec<usize> = ["<a", "<ab", "ab", "ab>", "b>"]
+                                       .iter()
+                                       .map(|g| hash(g) as usize % (1 << 20))
+                                       .collect();
+        assert_eq!(subwords.ngrams("ab"), expected);
+        assert_eq!(subwords.ngrams("é").len(), 3);
+        assert_eq!(hash("a"), 0xe40c292c);
+        assert_eq!(hash("é"), 0x3cfa68c1);
+    }
+
+    #[test]
+    fn test_compose() {
+        let subwords = subwords(100);
+        let vec = subwords.compose("cat").unwrap();
+        assert_eq!(vec.len(), 4);
+        assert_eq!(Some(vec), subwords.compose("cat"));
+        assert!(subwords.compose("cat") != subwords.compose("dog"));
+        assert_eq!(subwords.ngrams("").len(), 1);
+    }
+}
@@ -0,0 +1,283 @@
+use models::config::TrainingConfig;
+use models::cooccur::Cooccurrences;
+use models::linalg::{Matrix, Init};
+use models::random::{seeded_rng, SVD_STREAM};
+
+/// How many more directions than the rank the random projection keeps, so that the top
+/// singular vectors are captured accurately.
+const OVERSAMPLING: usize = 10;
+/// Rounds of multiplying by `A Aᵀ` to sharpen the projection towards the top singular
+/// vectors.
+const POWER_ITERATIONS: usize = 2;
+
+/// Replaces each count with its positive pointwise mutual information,
+/// `max(0, log(P(w, c) / (P(w) P(c))))`. Context probabilities are taken from their counts
+/// raised to the power `smoothing`, which stops rare contexts getting very high PMI.
+pub fn ppmi(cooccurrences: &Cooccurrences, smoothing: f32) -> Cooccurrences {
+    let cells = cooccurrences.nonzero();
+    let mut row_sums = vec![0.0f64; cooccurrences.rows()];
+    let mut col_sums = vec![0.0f64; cooccurrences.cols()];
+    for &(row, col, x) in &cells {
+        row_sums[row as usize] += x as f64;
+        col_sums[col as usize] += x as f64;
+    }
+    let smoothed: Vec<f64> = col_sums.iter().map(|x| x.powf(smoothing as f64)).collect();
+    let smoothed_total = smoothed.iter().fold(0.0, |x, y| x + y);
+
+    let mut ppmi = Cooccurrences::new(cooccurrences.rows(), cooccurrences.cols());
+    for &(row, col, x) in &cells {
+        let context = smoothed[col as usize] / smoothed_total;
+        let pmi = (x as f64 / (row_sums[row as usize] * context)).ln();
+        if pmi > 0.0 {
+            ppmi.add(row as usize, col as usize, pmi as f32);
+        }
+    }
+    ppmi
+}
+
+/// The leading singular values and vectors of a matrix, `A ≈ U diag(sigma) Vᵀ`.
+pub struct Svd {
+    /// One row per row of `A`, one column per singular value.
+    pub u: Matrix,
+    pub sigma: Vec<f32>,
+    /// One row per column of `A`, one column per singular value.
+    pub v: Matrix,
+}
+
+/// Finds the top `rank` singular values and vectors of `a` by randomized projection, after
+/// Halko, Martinsson and Tropp: the rows of `a` are projected onto a few random directions,
+/// the result is orthonormalised, and the small matrix left over is decomposed exactly.
+pub fn truncated_svd(a: &Cooccurrences, rank: usize, seed: u64) -> Svd {
+    let (m, n) = (a.rows(), a.cols());
+    let l = (rank + OVERSAMPLING).min(m).min(n);
+    let rank = rank.min(l);
+    let cells = a.nonzero();
+
+    let mut rng = seeded_rng(seed, SVD_STREAM, 0);
+    let omega = Matrix::random(n, l, Init::Gaussian, 1.0, &mut rng);
+    let mut q = multiply(&cells, m, &omega);
+    orthonormalise(&mut q);
+    for _ in 0..POWER_ITERATIONS {
+        let mut z = multiply_transpose(&cells, n, &q);
+        orthonormalise(&mut z);
+        q = multiply(&cells, m, &z);
+        orthonormalise(&mut q);
+    }
+
+    // B = Qᵀ A is only l × n, and the eigenvectors of B Bᵀ are its left singular vectors
+    let b_t = multiply_transpose(&cells, n, &q);
+    let mut gram = vec![0.0f64; l * l];
+    for j in 0..n {
+        let row = &b_t[j];
+        for x in 0..l {
+            for y in 0..l {
+                gram[x * l + y] += row[x] as f64 * row[y] as f64;
+            }
+        }
+    }
+    let (eigenvalues, eigenvectors) = symmetric_eigen(gram, l);
+
+    let mut order: Vec<usize> = (0..l).collect();
+    order.sort_by(|&x, &y| eigenvalues[y].partial_cmp(&eigenvalues[x]).unwrap());
+    order.truncate(rank);
+    let sigma: Vec<f32> = order.iter().map(|&i| eigenvalues[i].max(0.0).sqrt() as f32).collect();
+
+    // U = Q W and V = Bᵀ W / sigma, for the chosen eigenvectors W
+    let mut w = Matrix::from_elem(l, rank, 0.0);
+    for (k, &i) in order.iter().enumerate() {
+        for x in 0..l {
+            w[x][k] = eigenvectors[x * l + i] as f32;
+        }
+    }
+    let mut v = &b_t * &w;
+    let inverse: Vec<f32> = sigma.iter().map(|&s| if s > 0.0 { 1.0 / s } else { 0.0 }).collect();
+    scale_columns(&mut v, &inverse);
+
+    Svd {
+        u: &q * &w,
+        sigma: sigma,
+        v: v,
+    }
+}
+
+/// Trains word and context vectors from the PPMI of `cooccurrences`, as the left and right
+/// singular vectors weighted by the singular values raised to `config.eigenvalue_weight`.
+pub fn train(cooccurrences: &Cooccurrences,
+             config: &TrainingConfig,
+             seed: u64)
+             -> (Matrix, Matrix) {
+    let svd = truncated_svd(&ppmi(cooccurrences, config.smoothing), config.rank, seed);
+    let weights: Vec<f32> = svd.sigma.iter().map(|s| s.powf(config.eigenvalue_weight)).collect();
+
+    let (mut words, mut contexts) = (svd.u, svd.v);
+    scale_columns(&mut words, &weights);
+    scale_columns(&mut contexts, &weights);
+    (words, contexts)
+}
+
+fn scale_columns(m: &mut Matrix, weights: &[f32]) {
+    for r in 0..m.rows() {
+        for (x, w) in m[r].iter_mut().zip(weights.iter()) {
+            *x *= *w;
+        }
+    }
+}
+
+/// `A X` for sparse `A` with `rows` rows.
+fn multiply(a: &[(u32, u32, f32)], rows: usize, x: &Matrix) -> Matrix {
+    let mut out = Matrix::from_elem(rows, x.cols(), 0.0);
+    for &(i, j, weight) in a {
+        out[i as usize].axpy(weight, &x[j as usize]);
+    }
+    out
+}
+
+/// `Aᵀ X` for sparse `A` with `rows` columns.
+fn multiply_transpose(a: &[(u32, u32, f32)], rows: usize, x: &Matrix) -> Matrix {
+    let mut out = Matrix::from_elem(rows, x.cols(), 0.0);
+    for &(i, j, weight) in a {
+        out[j as usize].axpy(weight, &x[i as usize]);
+    }
+    out
+}
+
+/// Makes the columns of `m` orthonormal by modified Gram-Schmidt, projecting each column
+/// out twice to keep the rounding errors down. Columns that turn out to be dependent on
+/// earlier ones are zeroed.
+fn orthonormalise(m: &mut Matrix) {
+    let column_dot = |m: &Matrix, c: usize, p: usize| {
+        m.column(c).zip(m.column(p)).fold(0.0f64, |sum, (x, y)| sum + (x * y) as f64)
+    };
+    for c in 0..m.cols() {
+        for _ in 0..2 {
+            for p in 0..c {
+                let dot = column_dot(m, c, p) as f32;
+                for r in 0..m.rows() {
+                    m[r][c] -= dot * m[r][p];
+                }
+            }
+        }
+        let norm = column_dot(m, c, c).sqrt();
+        for r in 0..m.rows() {
+            m[r][c] = if norm > 1e-6 { (m[r][c] as f64 / norm) as f32 } else { 0.0 };
+        }
+    }
+}
+
+/// The eigenvalues and eigenvectors of the symmetric `n` × `n` matrix `a`, by cyclic
+/// Jacobi rotations. Eigenvector `i` is column `i` of the second matrix.
+fn symmetric_eigen(mut a: Vec<f64>, n: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut v = vec![0.0; n * n];
+    for i in 0..n {
+        v[i * n + i] = 1.0;
+    }
+
+    for _ in 0..100 {
+        let off = (0..n * n).filter(|&i| i / n != i % n).fold(0.0, |sum, i| sum + a[i] * a[i]);
+        let total = a.iter().fold(0.0, |sum, x| sum + x * x);
+        if off <= 1e-24 * total {
+            break;
+        }
+
+        for p in 0..n {
+            for q in (p + 1)..n {
+                let apq = a[p * n + q];
+                if apq == 0.0 {
+                    continue;
+                }
+                let theta = (a[q * n + q] - a[p * n + p]) / (2.0 * apq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                for k in 0..n {
+                    let (akp, akq) = (a[k * n + p], a[k * n + q]);
+                    a[k * n + p] = c * akp - s * akq;
+                    a[k * n + q] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[p * n + k], a[q * n + k]);
+                    a[p * n + k] = c * apk - s * aqk;
+                    a[q * n + k] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (v[k * n + p], v[k * n + q]);
+                    v[k * n + p] = c * vkp - s * vkq;
+                    v[k * n + q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    ((0..n).map(|i| a[i * n + i]).collect(), v)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ppmi, truncated_svd, symmetric_eigen};
+    use models::cooccur::Cooccurrences;
+
+    #[test]
+    fn test_ppmi() {
+        let mut cooc = Cooccurrences::new(2, 2);
+        cooc.add(0, 0, 2.0);
+        cooc.add(0, 1, 2.0);
+        cooc.add(1, 1, 4.0);
+
+        // without smoothing, P(c) is 0.25 and 0.75, and P(w) is a half each
+        let unsmoothed = ppmi(&cooc, 1.0);
+        assert!((unsmoothed.get(0, 0) - 2f32.ln()).abs() < 1e-6);
+        assert_eq!(unsmoothed.get(0, 1), 0.0);
+        assert!((unsmoothed.get(1, 1) - (4.0f32 / 3.0).ln()).abs() < 1e-6);
+        assert_eq!(unsmoothed.cells(), 2);
+
+        // smoothing raises the probability of the rarer context, lowering its PMI
+        assert!(ppmi(&cooc, 0.5).get(0, 0) < unsmoothed.get(0, 0));
+    }
+
+    #[test]
+    fn test_symmetric_eigen() {
+        let a = vec![2.0, 1.0, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0, 5.0];
+        let (values, vectors) = symmetric_eigen(a, 3);
+        let mut sorted = values.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (value, expected) in sorted.iter().zip([1.0, 3.0, 5.0].iter()) {
+            assert!((value - expected).abs() < 1e-9);
+        }
+
+        let i = values.iter().position(|v| (v - 3.0).abs() < 1e-9).unwrap();
+        assert!((vectors[i].abs() - 0.5f64.sqrt()).abs() < 1e-9);
+        assert!((vectors[3 + i] - vectors[i]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_truncated_svd() {
+        // a rank two matrix, the sum of two outer products
+        let (x1, y1) = ([1.0, 2.0, 0.0, 1.0, 3.0, 1.0], [1.0, 0.0, 2.0, 1.0, 1.0]);
+        let (x2, y2) = ([0.0, 1.0, 1.0, 2.0, 0.0, 1.0], [0.0, 3.0, 1.0, 0.0, 2.0]);
+        let mut a = Cooccurrences::new(6, 5);
+        for i in 0..6 {
+            for j in 0..5 {
+                let x = x1[i] * y1[j] + x2[i] * y2[j];
+                if x != 0.0 {
+                    a.add(i, j, x);
+                }
+            }
+        }
+
+        let svd = truncated_svd(&a, 2, 1);
+        assert_eq!(svd.sigma.len(), 2);
+        assert!(svd.sigma[0] >= svd.sigma[1]);
+        for i in 0..6 {
+            for j in 0..5 {
+                let estimate = (0..2).fold(0.0, |sum, k| {
+                    sum + svd.u[i][k] * svd.sigma[k] * svd.v[j][k]
+                });
+                assert!((estimate - a.get(i, j)).abs() < 1e-3);
+            }
+        }
+
+        let again = truncated_svd(&a, 2, 1);
+        assert_eq!(svd.sigma, again.sigma);
+    }
+}
@@ -0,0 +1,93 @@
+use models::als;
+use models::config::{TrainingConfig, Progress};
+use models::glove;
+use models::models::{LanguageModel, LanguageModelBuilder};
+use models::svd;
+
+/// A way of turning a builder's co-occurrence counts into word vectors.
+pub trait Trainer {
+    /// Trains a model on `builder`'s counts. Trainers that work in epochs call `progress`
+    /// after each one.
+    fn train(&self,
+             builder: &LanguageModelBuilder,
+             config: &TrainingConfig,
+             progress: &mut FnMut(&Progress))
+             -> LanguageModel;
+}
+
+/// GloVe's weighted least squares, fitted by AdaGrad over the non-zero counts.
+pub struct GloveTrainer;
+
+/// GloVe's weighted least squares, solved exactly for one side's vectors at a time.
+pub struct AlsTrainer;
+
+/// A truncated SVD of the counts' positive pointwise mutual information, which is quicker
+/// and needs no epochs.
+pub struct SvdTrainer;
+
+impl Trainer for GloveTrainer {
+    fn train(&self,
+             builder: &LanguageModelBuilder,
+             config: &TrainingConfig,
+             progress: &mut FnMut(&Progress))
+             -> LanguageModel {
+        let glove = glove::train(builder.cooccurrences(), config, builder.random_seed(), progress);
+        builder.model(&glove.words.to_matrix(), &glove.contexts.to_matrix(), config)
+    }
+}
+
+impl Trainer for AlsTrainer {
+    fn train(&self,
+             builder: &LanguageModelBuilder,
+             config: &TrainingConfig,
+             progress: &mut FnMut(&Progress))
+             -> LanguageModel {
+        let als = als::train(builder.cooccurrences(), config, builder.random_seed(), progress);
+        builder.model(&als.words, &als.contexts, config)
+    }
+}
+
+impl Trainer for SvdTrainer {
+    fn train(&self,
+             builder: &LanguageModelBuilder,
+             config: &TrainingConfig,
+             _: &mut FnMut(&Progress))
+             -> LanguageModel {
+        let (words, contexts) = svd::train(builder.cooccurrences(), config, builder.random_seed());
+        builder.model(&words, &contexts, config)
+    }
+}
+
+/// The names of every way there is to train vectors: the trainers `trainer` knows, and the
+/// word2vec architectures, which train on the corpus instead.
+pub const TRAINERS: &'static [&'static str] = &["glove", "als", "svd", "skipgram", "cbow"];
+
+/// Whether `name` is one of `TRAINERS`.
+pub fn is_trainer(name: &str) -> bool {
+    TRAINERS.contains(&name)
+}
+
+/// The trainer called `name`, if it trains on a builder's counts.
+pub fn trainer(name: &str) -> Option<Box<Trainer>> {
+    match name {
+        "glove" => Some(Box::new(GloveTrainer)),
+        "als" => Some(Box::new(AlsTrainer)),
+        "svd" => Some(Box::new(SvdTrainer)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{trainer, is_trainer, TRAINERS};
+    use models::word2vec::Architecture;
+
+    #[test]
+    fn test_trainers() {
+        for name in TRAINERS {
+            assert!(trainer(name).is_some() != Architecture::from_name(name).is_some());
+        }
+        assert!(is_trainer("cbow"));
+        assert!(!is_trainer("word2vec"));
+    }
+}
@@ -0,0 +1,189 @@
+use models::models::{read_raw, write_raw, read_byte};
+use models::random::{seeded_rng, WINDOW_STREAM};
+use rand::XorShiftRng;
+use std::io::{self, BufReader, Read, Write};
+
+/// The kinds of boundary in a corpus, from narrowest to widest. A builder's window scope
+/// is the narrowest boundary that its context windows may not cross.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Boundary {
+    Sentence,
+    Paragraph,
+    Document,
+}
+
+/// How much a co-occurrence counts for, given how far apart the two words are.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ContextWeighting {
+    /// `1 / distance`, as in GloVe.
+    Harmonic,
+    /// Every word in the window counts fully.
+    Uniform,
+    /// Falls linearly from 1 for neighbours to `1 / radius` at the edge of the window.
+    Linear,
+    /// As in word2vec, each word gets a window of random size between 1 and the radius, and
+    /// every word inside that counts fully. On average this weights a co-occurrence the same
+    /// as `Linear`.
+    DynamicWindow,
+}
+
+impl ContextWeighting {
+    pub fn weight(&self, distance: usize, radius: usize) -> f32 {
+        debug_assert!(distance != 0 && distance <= radius);
+        match *self {
+            ContextWeighting::Harmonic => 1.0 / distance as f32,
+            ContextWeighting::Uniform | ContextWeighting::DynamicWindow => 1.0,
+            ContextWeighting::Linear => (radius - distance + 1) as f32 / radius as f32,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ContextWeighting> {
+        match name {
+            "harmonic" => Some(ContextWeighting::Harmonic),
+            "uniform" => Some(ContextWeighting::Uniform),
+            "linear" => Some(ContextWeighting::Linear),
+            "dynamic" => Some(ContextWeighting::DynamicWindow),
+            _ => None,
+        }
+    }
+}
+
+/// Everything that decides how a context window is counted. Builders can only be merged
+/// when these match, and they're saved along with the counts.
+#[derive(Clone, PartialEq, Debug)]
+pub struct WindowSettings {
+    /// How many words before a word are counted as its context.
+    pub left: usize,
+    /// How many words after a word are counted as its context.
+    pub right: usize,
+    pub scope: Boundary,
+    pub weighting: ContextWeighting,
+    /// Whether a context is a word at one particular offset, rather than just a word.
+    pub positional: bool,
+    pub seed: u64,
+}
+
+impl WindowSettings {
+    /// How many recent words need to be kept to count the next word's context.
+    pub fn span(&self) -> usize {
+        self.left.max(self.right)
+    }
+
+    /// The number of distinct contexts each word gives rise to: one for every offset in the
+    /// window when contexts are positional, otherwise just the one.
+    pub fn contexts_per_word(&self) -> usize {
+        if self.positional {
+            self.left + self.right
+        } else {
+            1
+        }
+    }
+
+    /// The context index for `word` seen `distance` words to the left of a target.
+    pub fn left_context(&self, word: usize, distance: usize) -> usize {
+        if self.positional {
+            word * self.contexts_per_word() + self.left - distance
+        } else {
+            word
+        }
+    }
+
+    /// The context index for `word` seen `distance` words to the right of a target.
+    pub fn right_context(&self, word: usize, distance: usize) -> usize {
+        if self.positional {
+            word * self.contexts_per_word() + self.left + distance - 1
+        } else {
+            word
+        }
+    }
+
+    /// The random number generator for dynamic window sizes within one document.
+    pub fn rng(&self, document: u64) -> XorShiftRng {
+        seeded_rng(self.seed, WINDOW_STREAM, document)
+    }
+
+    pub fn write<W: Write>(&self, file: &mut W) -> io::Result<()> {
+        try!(write_raw(self.left as u64, file));
+        try!(write_raw(self.right as u64, file));
+        try!(write_raw(self.scope as u8, file));
+        try!(write_raw(self.weighting as u8, file));
+        try!(write_raw(self.positional as u8, file));
+        try!(write_raw(self.seed, file));
+        try!(file.write(&[b'\n']));
+        Ok(())
+    }
+
+    pub fn read<R: Read>(file: &mut BufReader<R>) -> io::Result<WindowSettings> {
+        let left = unsafe { try!(read_raw::<u64, _>(file)) };
+        let right = unsafe { try!(read_raw::<u64, _>(file)) };
+        let scope = match unsafe { try!(read_raw::<u8, _>(file)) } {
+            0 => Boundary::Sentence,
+            1 => Boundary::Paragraph,
+            2 => Boundary::Document,
+            _ => return Err(invalid("unknown window scope")),
+        };
+        let weighting = match unsafe { try!(read_raw::<u8, _>(file)) } {
+            0 => ContextWeighting::Harmonic,
+            1 => ContextWeighting::Uniform,
+            2 => ContextWeighting::Linear,
+            3 => ContextWeighting::DynamicWindow,
+            _ => return Err(invalid("unknown context weighting")),
+        };
+        let positional = unsafe { try!(read_raw::<u8, _>(file)) } != 0;
+        let seed = unsafe { try!(read_raw::<u64, _>(file)) };
+        try!(read_byte(b'\n', file));
+        if positional && left + right == 0 {
+            return Err(invalid("positional contexts with an empty window"));
+        }
+
+        Ok(WindowSettings {
+            left: left as usize,
+            right: right as usize,
+            scope: scope,
+            weighting: weighting,
+            positional: positional,
+            seed: seed,
+        })
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{WindowSettings, Boundary};
+    use super::ContextWeighting::*;
+
+    #[test]
+    fn test_weights() {
+        assert_eq!(Harmonic.weight(4, 10), 0.25);
+        assert_eq!(Uniform.weight(4, 10), 1.0);
+        assert_eq!(Linear.weight(1, 4), 1.0);
+        assert_eq!(Linear.weight(4, 4), 0.25);
+        assert_eq!(DynamicWindow.weight(3, 4), 1.0);
+    }
+
+    #[test]
+    fn test_positional_contexts() {
+        let mut settings = WindowSettings {
+            left: 2,
+            right: 3,
+            scope: Boundary::Sentence,
+            weighting: Uniform,
+            positional: false,
+            seed: 0,
+        };
+        assert_eq!(settings.left_context(4, 2), 4);
+        assert_eq!(settings.right_context(4, 3), 4);
+
+        // word 4's contexts are at offsets -2, -1, 1, 2, 3
+        settings.positional = true;
+        assert_eq!(settings.contexts_per_word(), 5);
+        assert_eq!(settings.left_context(4, 2), 20);
+        assert_eq!(settings.left_context(4, 1), 21);
+        assert_eq!(settings.right_context(4, 1), 22);
+        assert_eq!(settings.right_context(4, 3), 24);
+    }
+}
@@ -0,0 +1,328 @@
+use models::config::TrainingConfig;
+use models::hogwild::{HogwildMatrix, values};
+use models::linalg::Matrix;
+use models::random::{seeded_rng, INIT_STREAM, WORD2VEC_STREAM};
+use models::subword::Subwords;
+use rand::{Rng, XorShiftRng};
+
+/// The number of entries in the table negative samples are drawn from. Each word gets a
+/// share in proportion to its weight, so rarer words need a big table to be represented.
+const TABLE_SIZE: usize = 10000000;
+/// Dot products beyond this are treated as certain when computing the loss.
+const MAX_EXP: f32 = 6.0;
+
+/// How word2vec predicts words from their neighbours.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Architecture {
+    /// Each word predicts each of the words around it.
+    SkipGram,
+    /// The mean of the words around each word predicts it, a "continuous bag of words".
+    Cbow,
+}
+
+impl Architecture {
+    pub fn from_name(name: &str) -> Option<Architecture> {
+        match name {
+            "skipgram" => Some(Architecture::SkipGram),
+            "cbow" => Some(Architecture::Cbow),
+            _ => None,
+        }
+    }
+}
+
+/// A table of word indices with each word appearing in proportion to its count raised to
+/// the power 0.75, so that picking an entry at random draws from the smoothed unigram
+/// distribution.
+pub struct UnigramTable {
+    table: Vec<u32>,
+}
+
+impl UnigramTable {
+    pub fn new(counts: &[u64]) -> UnigramTable {
+        let weights: Vec<f64> = counts.iter().map(|&c| (c as f64).powf(0.75)).collect();
+        let total = weights.iter().fold(0.0, |x, y| x + y);
+        let size = if counts.is_empty() { 0 } else { TABLE_SIZE };
+
+        let mut table = Vec::with_capacity(size);
+        let (mut word, mut cumulative) = (0, 0.0);
+        for i in 0..size {
+            // move on to the next word once this one has had its share of the table
+            while word + 1 < weights.len() &&
+                  (i as f64 + 0.5) / size as f64 > (cumulative + weights[word]) / total {
+                cumulative += weights[word];
+                word += 1;
+            }
+            table.push(word as u32);
+        }
+        UnigramTable { table: table }
+    }
+
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        self.table[rng.gen_range(0, self.table.len())] as usize
+    }
+}
+
+/// The parameters of a word2vec model trained with negative sampling: an input vector for
+/// each word, which are the vectors kept at the end, and an output vector for each word as
+/// something to be predicted.
+///
+/// Training threads share the model and update the vectors through `&self` all at once
+/// without any locking, as in Hogwild.
+pub struct Word2Vec {
+    architecture: Architecture,
+    seed: u64,
+    rank: usize,
+    window: usize,
+    negative: usize,
+    input: HogwildMatrix,
+    output: HogwildMatrix,
+    table: UnigramTable,
+    /// The chance of keeping each word each time it's seen, which is lower for very common
+    /// words.
+    keep: Vec<f32>,
+    /// With subwords, how words are split into n-grams, and the n-grams of each word. The
+    /// n-gram vectors are kept apart in `ngram_vectors` while training.
+    subwords: Option<Subwords>,
+    ngrams: Vec<Vec<usize>>,
+    ngram_vectors: HogwildMatrix,
+}
+
+impl Word2Vec {
+    /// A model for words with the given corpus `counts`, looking `window` words either side
+    /// of each, with starting vectors drawn from `seed` as `config` says.
+    pub fn new(architecture: Architecture,
+               counts: &[u64],
+               window: usize,
+               config: &TrainingConfig,
+               seed: u64)
+               -> Word2Vec {
+        assert!(window > 0, "word2vec needs a window of at least one word");
+        let (words, rank) = (counts.len(), config.rank);
+        let scale = config.init_scale / rank as f32;
+        let mut rng = seeded_rng(seed, INIT_STREAM, 0);
+
+        let total = counts.iter().fold(0, |x, y| x + y) as f32;
+        let threshold = config.subsample * total;
+        let keep = counts.iter()
+                         .map(|&c| {
+                             if threshold <= 0.0 || c == 0 {
+                                 1.0
+                             } else {
+                                 ((c as f32 / threshold).sqrt() + 1.0) * threshold / c as f32
+                             }
+                         })
+                         .collect();
+
+        Word2Vec {
+            architecture: architecture,
+            seed: seed,
+            rank: rank,
+            window: window,
+            negative: config.negative,
+            input: HogwildMatrix::new(&Matrix::random(words, rank, config.init, scale, &mut rng)),
+            output: HogwildMatrix::new(&Matrix::from_elem(words, rank, 0.0)),
+            table: UnigramTable::new(counts),
+            keep: keep,
+            subwords: None,
+            ngrams: vec![],
+            ngram_vectors: HogwildMatrix::new(&Matrix::from_elem(0, rank, 0.0)),
+        }
+    }
+
+    /// Learns vectors for the character n-grams of `words` too, and makes each word's input
+    /// vector the mean of its own vector and those of its n-grams, as in fastText.
+    pub fn learn_subwords(mut self, words: &[String], config: &TrainingConfig) -> Word2Vec {
+        let subwords = Subwords::new(config, &mut seeded_rng(self.seed, INIT_STREAM, 1));
+        self.ngrams = words.iter().map(|w| subwords.ngrams(w)).collect();
+        self.ngram_vectors = HogwildMatrix::new(&subwords.vectors);
+        self.subwords = Some(subwords);
+        self
+    }
+
+    /// The vector `word` presents as input: its own vector, averaged with those of its
+    /// n-grams when learning subwords.
+    pub fn embed(&self, word: usize) -> Vec<f32> {
+        let mut vec = values(self.input.row(word));
+        if self.subwords.is_some() {
+            let ngrams = &self.ngrams[word];
+            for &ngram in ngrams {
+                for (x, y) in vec.iter_mut().zip(self.ngram_vectors.row(ngram).iter()) {
+                    *x += y.get();
+                }
+            }
+            for x in &mut vec {
+                *x /= (ngrams.len() + 1) as f32;
+            }
+        }
+        vec
+    }
+
+    /// Every word's input vector, as `embed` gives it, one row for each word.
+    pub fn input_vectors(&self) -> Matrix {
+        let rows: Vec<_> = (0..self.input.rows()).map(|word| self.embed(word)).collect();
+        Matrix::from_rows(&rows, self.rank)
+    }
+
+    /// Every word's output vector.
+    pub fn output_vectors(&self) -> Matrix {
+        self.output.to_matrix()
+    }
+
+    /// With subwords, the n-grams along with the vectors learnt for them.
+    pub fn subwords(&self) -> Option<Subwords> {
+        self.subwords.as_ref().map(|s| s.with_vectors(self.ngram_vectors.to_matrix()))
+    }
+
+    /// Adds `step` to every vector that makes up `word`'s input vector.
+    fn step_input(&self, word: usize, step: &[f32]) {
+        self.input.axpy(word, 1.0, step);
+        if self.subwords.is_some() {
+            for &ngram in &self.ngrams[word] {
+                self.ngram_vectors.axpy(ngram, 1.0, step);
+            }
+        }
+    }
+
+    /// The random numbers for training on document number `document` in `epoch`. Each has
+    /// its own stream, so that a single thread always trains the same model.
+    pub fn rng(&self, epoch: usize, document: usize) -> XorShiftRng {
+        seeded_rng(self.seed, WORD2VEC_STREAM, (epoch as u64) << 32 | document as u64)
+    }
+
+    /// Drops some of the most common words from a sentence, at random. They carry little
+    /// information and would otherwise take up most of the training time.
+    pub fn subsample<R: Rng>(&self, sentence: &mut Vec<usize>, rng: &mut R) {
+        sentence.retain(|&w| self.keep[w] >= 1.0 || rng.gen::<f32>() < self.keep[w]);
+    }
+
+    /// Takes one step for each word of `sentence`, given as word indices, at the learning
+    /// rate `rate`. Each word looks at a window of a random size up to the model's window, as
+    /// in word2vec, so nearer words count for more. Returns the total loss and the number of
+    /// predictions it was summed over.
+    pub fn train_sentence<R: Rng>(&self,
+                                  sentence: &[usize],
+                                  rate: f32,
+                                  rng: &mut R)
+                                  -> (f64, usize) {
+        let (mut loss, mut predictions) = (0.0, 0);
+        let mut hidden = vec![0.0; self.rank];
+        for (i, &word) in sentence.iter().enumerate() {
+            let radius = rng.gen_range(0, self.window) + 1;
+            let start = i.saturating_sub(radius);
+            let end = (i + radius + 1).min(sentence.len());
+            let contexts = (start..end).filter(|&j| j != i).map(|j| sentence[j]);
+
+            match self.architecture {
+                Architecture::SkipGram => {
+                    for context in contexts {
+                        let input = self.embed(word);
+                        loss += self.predict(&input, &mut hidden, context, rate, rng);
+                        self.step_input(word, &hidden);
+                        predictions += 1;
+                    }
+                }
+                Architecture::Cbow => {
+                    let contexts: Vec<usize> = contexts.collect();
+                    if contexts.is_empty() {
+                        continue;
+                    }
+                    let mut mean = vec![0.0; self.rank];
+                    for &context in &contexts {
+                        for (x, y) in mean.iter_mut().zip(self.embed(context).iter()) {
+                            *x += *y / contexts.len() as f32;
+                        }
+                    }
+                    loss += self.predict(&mean, &mut hidden, word, rate, rng);
+                    for &context in &contexts {
+                        self.step_input(context, &hidden);
+                    }
+                    predictions += 1;
+                }
+            }
+        }
+        (loss, predictions)
+    }
+
+    /// Nudges the output vectors towards predicting `target` from `input`, and away from
+    /// predicting `negative` words drawn from the unigram table. The step for `input` is
+    /// left in `gradient`, and the loss is returned.
+    fn predict<R: Rng>(&self,
+                       input: &[f32],
+                       gradient: &mut [f32],
+                       target: usize,
+                       rate: f32,
+                       rng: &mut R)
+                       -> f64 {
+        for g in gradient.iter_mut() {
+            *g = 0.0;
+        }
+
+        let mut loss = 0.0;
+        for d in 0..self.negative + 1 {
+            let (output, label) = if d == 0 {
+                (target, 1.0)
+            } else {
+                let sample = self.table.sample(rng);
+                if sample == target {
+                    continue;
+                }
+                (sample, 0.0)
+            };
+
+            let f = self.output.dot(output, input).max(-MAX_EXP).min(MAX_EXP);
+            let p = 1.0 / (1.0 + (-f).exp());
+            loss -= if label > 0.0 { p.ln() } else { (1.0 - p).ln() } as f64;
+
+            let step = rate * (label - p);
+            let out = self.output.row(output);
+            for k in 0..input.len() {
+                let x = out[k].get();
+                gradient[k] += step * x;
+                out[k].set(x + step * input[k]);
+            }
+        }
+        loss
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{UnigramTable, Word2Vec, Architecture};
+    use models::config::TrainingConfig;
+    use models::random::seeded_rng;
+
+    #[test]
+    fn test_unigram_table() {
+        let table = UnigramTable::new(&[81, 16, 0, 1]);
+        let mut counts = [0; 4];
+        for &w in &table.table {
+            counts[w as usize] += 1;
+        }
+        // 81^0.75 = 27, 16^0.75 = 8 and 1^0.75 = 1
+        let expected = [27.0, 8.0, 0.0, 1.0];
+        for (&count, &weight) in counts.iter().zip(expected.iter()) {
+            let share = count as f64 / table.table.len() as f64;
+            assert!((share - weight / 36.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_learns_neighbours() {
+        let config = TrainingConfig {
+            rank: 10,
+            negative: 3,
+            subsample: 0.0,
+            ..TrainingConfig::default()
+        };
+        for &architecture in &[Architecture::SkipGram, Architecture::Cbow] {
+            let model = Word2Vec::new(architecture, &[100, 100, 100, 100], 1, &config, 1);
+            let mut rng = seeded_rng(1, 0, 0);
+            let (first, _) = model.train_sentence(&[0, 1, 0, 1, 2, 3, 2, 3], 0.1, &mut rng);
+            let mut last = first;
+            for _ in 0..200 {
+                last = model.train_sentence(&[0, 1, 0, 1, 2, 3, 2, 3], 0.1, &mut rng).0;
+            }
+            assert!(last < first / 2.0);
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use super::models::{LanguageModel, WordVec};
 use self::Token::*;
-use super::mayberef::MaybeRef::{self, Val, Ref};
+use super::mayberef::MaybeRef::{self, Val};
 use std::fmt::{Display, Formatter, Error};
 
 pub fn parse(expr: &str, model: &LanguageModel) -> Result<WordVec, String> {
@@ -48,7 +48,7 @@ impl Display for Token {
     }
 }
 
-fn get_word<'a>(model: &'a LanguageModel, word: &str) -> Result<&'a WordVec, String> {
+fn get_word<'a>(model: &'a LanguageModel, word: &str) -> Result<MaybeRef<'a, WordVec>, String> {
     model.get(word).ok_or_else(|| format!("'{}' is not present in the language model", word))
 }
 
@@ -108,10 +108,7 @@ fn atom<'a, I>(tokens: &mut Tokens<I>,
                 None => return Err("Unclosed parentheses".to_string()),
             }
         }
-        Word(word) => {
-            let vec = try!(get_word(model, &word));
-            Ref(vec)
-        }
+        Word(word) => try!(get_word(model, &word)),
         _ => return Err(format!("Invalid token {}", token)),
     };
 
@@ -152,7 +149,7 @@ impl<I> Tokens<I> where I: Iterator<Item = char>
         }
 
         match self.peek_char() {
-            Some('a'...'z') => return Some(self.word()),
+            Some(c) if c.is_alphabetic() => return Some(self.word()),
             Some('0'...'9') => return Some(self.number()),
             None => return None,
             _ => {}
@@ -193,10 +190,14 @@ impl<I> Tokens<I> where I: Iterator<Item = char>
         self.next_char.take().unwrap()
     }
 
+    // the vocabulary is lowercase, as the tokenizers leave it
     fn word(&mut self) -> Token {
         let mut token = String::new();
-        while let Some('a'...'z') = self.peek_char() {
-            token.push(self.take().unwrap());
+        while let Some(c) = self.peek_char() {
+            if !(c.is_alphabetic() || c == '\'') {
+                break;
+            }
+            token.extend(self.take().unwrap().to_lowercase());
         }
 
         Word(token)
@@ -231,7 +232,7 @@ mod test {
     use super::Tokens;
     use super::Token::*;
     use super::parse;
-    use super::super::models::{WordVec, LanguageModel, LanguageModelBuilder};
+    use super::super::models::{WordVec, LanguageModel, LanguageModelBuilder, TrainingConfig};
 
     #[test]
     fn single_character() {
@@ -251,6 +252,12 @@ mod test {
         assert_eq!(s, vec![Word("abc".to_string()), Word("def".to_string())]);
     }
 
+    #[test]
+    fn uppercase_token() {
+        let s = Tokens::from("The CAFÉ".chars()).collect::<Vec<_>>();
+        assert_eq!(s, vec![Word("the".to_string()), Word("café".to_string())]);
+    }
+
     #[test]
     fn plus_tokens() {
         let s = Tokens::from("   abc  +def".chars()).collect::<Vec<_>>();
@@ -283,18 +290,21 @@ mod test {
             }
         }
 
-        builder.build()
+        builder.build(&TrainingConfig::default())
+    }
+
+    // float addition isn't associative, so regrouped sums only agree to within rounding
+    fn assert_close(lhs: &str, rhs: &str, model: &LanguageModel) {
+        let (lhs, rhs) = (parse(lhs, model).unwrap(), parse(rhs, model).unwrap());
+        assert!(lhs.distance(&rhs) < 1e-5, "{:?} != {:?}", lhs, rhs);
     }
 
     #[test]
     fn test_addition() {
         let model = get_model();
-        assert_eq!(parse("a - b + c", &model).unwrap(),
-                   parse("(a - b) + c", &model).unwrap());
-        assert_eq!(parse("a + (b - c)", &model).unwrap(),
-                   parse("(a + b) - c", &model).unwrap());
-        assert_eq!(parse("a + b - c)", &model).unwrap(),
-                   parse("a - c + b", &model).unwrap());
+        assert_close("a - b + c", "(a - b) + c", &model);
+        assert_close("a + (b - c)", "(a + b) - c", &model);
+        assert_close("a + b - c)", "a - c + b", &model);
         assert!(parse("a + b", &model) != parse("a - b", &model));
     }
 
@@ -1,23 +1,133 @@
 extern crate time;
 
-use std::fs::{File, read_dir};
+use std::fs::File;
 use std::io::{BufReader, BufRead, Read, Write};
 use std::io;
-use std::path::Path;
-use std::collections::HashMap;
-use models::LanguageModelBuilder;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+use models::{LanguageModel, LanguageModelBuilder, TrainingConfig, Progress, Monitor};
+use models::{Architecture, Word2Vec};
 use error::Error;
 
 
-pub fn find_most_common_words(corpus: &Path, num: usize) -> Vec<(String, u64)> {
-    let words = files(corpus).flat_map(|file| read_words(file));
-    let mut word_counts = HashMap::new();
-    for word in words {
-        *word_counts.entry(word).or_insert(0) += 1;
+/// Splits text into the lowercase words that make up the vocabulary. Both the vocabulary
+/// count and the co-occurrence pass must use the same tokenizer, or words counted in one
+/// will be missing from the other.
+pub trait Tokenizer: Sync {
+    fn tokenize(&self, text: &str) -> Vec<String>;
+}
+
+/// Words are runs of ASCII letters; everything else, including accented letters, is a
+/// separator.
+pub struct AsciiTokenizer;
+
+/// Words are runs of Unicode letters and combining marks, with apostrophes allowed
+/// between letters, so that "café", "naïve" and "l'homme" survive intact.
+pub struct UnicodeTokenizer;
+
+impl Tokenizer for AsciiTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        text.split(|c| {
+                match c {
+                    'a'...'z' | 'A'...'Z' => false,
+                    _ => true,
+                }
+            })
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_lowercase())
+            .collect()
+    }
+}
+
+impl Tokenizer for UnicodeTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        let mut words = vec![];
+        let mut word = String::new();
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c.is_alphabetic() || (!word.is_empty() && is_combining_mark(c)) {
+                word.extend(c.to_lowercase());
+                continue;
+            }
+
+            let joins_letters = match chars.peek() {
+                Some(&next) => next.is_alphabetic(),
+                None => false,
+            };
+            if (c == '\'' || c == '\u{2019}') && !word.is_empty() && joins_letters {
+                word.push('\'');
+                continue;
+            }
+
+            if !word.is_empty() {
+                words.push(word);
+                word = String::new();
+            }
+        }
+
+        if !word.is_empty() {
+            words.push(word);
+        }
+        words
+    }
+}
+
+fn is_combining_mark(c: char) -> bool {
+    match c {
+        '\u{0300}'...'\u{036F}' |
+        '\u{1AB0}'...'\u{1AFF}' |
+        '\u{1DC0}'...'\u{1DFF}' |
+        '\u{20D0}'...'\u{20FF}' |
+        '\u{FE20}'...'\u{FE2F}' => true,
+        _ => false,
+    }
+}
+
+pub fn segmentation(name: &str) -> Option<Segmentation> {
+    match name {
+        "sentence" => Some(Segmentation::Sentences),
+        "paragraph" => Some(Segmentation::Paragraphs),
+        _ => None,
     }
+}
 
+pub fn tokenizer(name: &str) -> Option<Box<Tokenizer>> {
+    match name {
+        "ascii" => Some(Box::new(AsciiTokenizer)),
+        "unicode" => Some(Box::new(UnicodeTokenizer)),
+        _ => None,
+    }
+}
+
+pub fn find_most_common_words(corpus: &[PathBuf],
+                              num: usize,
+                              tokenizer: &Tokenizer,
+                              threads: usize)
+                              -> Vec<(String, u64)> {
+    let mut word_counts = HashMap::new();
+    in_file_order(corpus,
+                  threads,
+                  || (),
+                  |_, _, file| {
+                      let mut file_counts = HashMap::new();
+                      for word in read_words(file, tokenizer) {
+                          *file_counts.entry(word).or_insert(0) += 1;
+                      }
+                      file_counts
+                  },
+                  |file_counts: HashMap<String, u64>| {
+                      for (word, count) in file_counts {
+                          *word_counts.entry(word).or_insert(0) += count;
+                      }
+                  });
+
+    // ties are broken alphabetically so that the vocabulary is the same on every run
     let mut counts: Vec<_> = word_counts.into_iter().collect();
-    counts.sort_by(|a, b| b.1.cmp(&a.1));
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
     counts.truncate(num);
 
     counts
@@ -49,59 +159,643 @@ pub fn load_most_common_words<R: Read>(file: R, num: usize) -> Result<Vec<(Strin
           .collect()
 }
 
-fn sentences<T: Read + 'static>(reader: BufReader<T>) -> Box<Iterator<Item = String>> {
-    Box::new(reader.split('.' as u8)
-                   .filter_map(|v| {
-                       let lowercase = v.unwrap();
-                       String::from_utf8(lowercase).ok().map(|s| s.to_lowercase())
-                   }))
-}
-
-fn read_words<R: BufRead + 'static>(reader: R) -> Box<Iterator<Item = String>> {
-    Box::new(reader.lines()
-                   .filter_map(|line| line.ok())
-                   .flat_map(|line| {
-                       line.split(|c| {
-                               match c {
-                                   'a'...'z' | 'A'...'Z' => false,
-                                   _ => true,
-                               }
-                           })
-                           .filter(|word| !word.is_empty())
-                           .map(|word| word.to_lowercase())
-                           .collect::<Vec<_>>()
-                           .into_iter()
-                   }))
-}
-
-fn files(path: &Path) -> Box<Iterator<Item = BufReader<File>>> {
-    Box::new(read_dir(path)
-                 .unwrap()
-                 .map(|path| path.unwrap().path())
-                 .filter(|path| path.to_str().unwrap().ends_with(".txt"))
-                 .map(|path| {
-                     let file = File::open(path).unwrap();
-                     BufReader::new(file)
-                 }))
-}
-
-pub fn create_model(corpus: &Path, words: Vec<String>) -> LanguageModelBuilder {
-    let mut builder = LanguageModelBuilder::new(10, words);
-
-    for sentence in files(corpus).flat_map(sentences) {
-        let mut acc = builder.new_sentence();
-
-        let words = sentence.split(|c| {
-                                match c {
-                                    'a'...'z' => false,
-                                    _ => true,
-                                }
-                            })
-                            .filter(|w| !w.is_empty());
-        for word in words {
-            acc.add_word(word);
+const ABBREVIATIONS: &'static [&'static str] = &["mr", "mrs", "ms", "dr", "prof", "st", "jr",
+                                                 "sr", "vs", "e.g", "i.e", "cf", "al", "fig",
+                                                 "no", "vol", "inc", "ltd", "co", "approx"];
+
+/// How a corpus file is divided into the units that context windows may not cross.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Segmentation {
+    /// Sentences end at '.', '?' or '!' followed by whitespace, unless the full stop belongs
+    /// to an abbreviation or an initial, or the next word starts in lowercase. Blank lines
+    /// always end a sentence.
+    Sentences,
+    /// Only blank lines separate units of text.
+    Paragraphs,
+}
+
+pub struct Segmenter {
+    mode: Segmentation,
+    abbreviations: HashSet<String>,
+}
+
+impl Segmenter {
+    pub fn new(mode: Segmentation) -> Segmenter {
+        Segmenter {
+            mode: mode,
+            abbreviations: ABBREVIATIONS.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    /// Returns the byte offset just past the end of the first sentence in `text`, if
+    /// `text` holds enough to tell where that is. `complete` says that no more text
+    /// follows, so anything left over is a sentence in its own right.
+    fn sentence_end(&self, text: &str, complete: bool) -> Option<usize> {
+        if self.mode == Segmentation::Paragraphs {
+            return None;
+        }
+
+        let mut chars = text.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if !is_terminator(c) {
+                continue;
+            }
+
+            let mut only_full_stop = c == '.';
+            let mut end = i + c.len_utf8();
+            while let Some(&(j, next)) = chars.peek() {
+                if !(is_terminator(next) || is_closing(next)) {
+                    break;
+                }
+                only_full_stop &= next != '?' && next != '!';
+                end = j + next.len_utf8();
+                chars.next();
+            }
+
+            if only_full_stop && self.is_abbreviation(&text[..i]) {
+                continue;
+            }
+
+            let rest = &text[end..];
+            if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+                // a decimal point, a url or something similar
+                continue;
+            }
+
+            match rest.trim_start().chars().next() {
+                Some(next) if next.is_lowercase() => continue,
+                Some(_) => return Some(end),
+                None if complete => return Some(end),
+                None => return None,
+            }
+        }
+
+        None
+    }
+
+    fn is_abbreviation(&self, before: &str) -> bool {
+        let start = before.rfind(|c: char| !(c.is_alphabetic() || c == '.'))
+                          .map(|i| i + before[i..].chars().next().unwrap().len_utf8())
+                          .unwrap_or(0);
+        let word = before[start..].to_lowercase();
+
+        word.chars().count() == 1 || word.contains('.') || self.abbreviations.contains(&word)
+    }
+}
+
+fn is_terminator(c: char) -> bool {
+    c == '.' || c == '?' || c == '!' || c == '\u{2026}'
+}
+
+fn is_closing(c: char) -> bool {
+    match c {
+        '"' | '\'' | ')' | ']' | '\u{201D}' | '\u{2019}' | '\u{BB}' => true,
+        _ => false,
+    }
+}
+
+/// Reads lines as UTF-8, replacing invalid bytes rather than dropping the whole line.
+fn lossy_lines<'a, R: BufRead + 'a>(mut reader: R) -> Box<Iterator<Item = String> + 'a> {
+    Box::new((0..).map(move |_| {
+                      let mut buffer = vec![];
+                      match reader.read_until(b'\n', &mut buffer) {
+                          Ok(0) | Err(_) => None,
+                          Ok(_) => Some(String::from_utf8_lossy(&buffer).into_owned()),
+                      }
+                  })
+                  .take_while(|line| line.is_some())
+                  .map(|line| line.unwrap()))
+}
+
+/// A piece of a corpus file, in the order it was read.
+#[derive(PartialEq, Debug)]
+pub enum Segment {
+    Sentence(String),
+    ParagraphBreak,
+}
+
+struct Sentences<'a> {
+    lines: Box<Iterator<Item = String> + 'a>,
+    segmenter: &'a Segmenter,
+    pending: String,
+    in_paragraph: bool,
+    paragraph_ending: bool,
+    finished: bool,
+}
+
+impl<'a> Sentences<'a> {
+    fn take_sentence(&mut self, complete: bool) -> Option<String> {
+        if self.pending.trim().is_empty() {
+            return None;
+        }
+
+        let end = match self.segmenter.sentence_end(&self.pending, complete) {
+            Some(end) => end,
+            None if complete => self.pending.len(),
+            None => return None,
+        };
+
+        let sentence = self.pending[..end].trim().to_string();
+        self.pending = self.pending[end..].to_string();
+        Some(sentence)
+    }
+}
+
+impl<'a> Iterator for Sentences<'a> {
+    type Item = Segment;
+
+    fn next(&mut self) -> Option<Segment> {
+        loop {
+            let complete = self.finished || self.paragraph_ending;
+            if let Some(sentence) = self.take_sentence(complete) {
+                self.in_paragraph = true;
+                return Some(Segment::Sentence(sentence));
+            }
+
+            if complete {
+                self.paragraph_ending = false;
+                if self.in_paragraph {
+                    self.in_paragraph = false;
+                    return Some(Segment::ParagraphBreak);
+                }
+                if self.finished {
+                    return None;
+                }
+            }
+
+            match self.lines.next() {
+                // a blank line ends the paragraph, along with any sentence left open
+                Some(ref line) if line.trim().is_empty() => self.paragraph_ending = true,
+                Some(line) => self.pending.push_str(&line),
+                None => self.finished = true,
+            }
+        }
+    }
+}
+
+fn sentences<'a, R: BufRead + 'a>(reader: R, segmenter: &'a Segmenter) -> Sentences<'a> {
+    Sentences {
+        lines: lossy_lines(reader),
+        segmenter: segmenter,
+        pending: String::new(),
+        in_paragraph: false,
+        paragraph_ending: false,
+        finished: false,
+    }
+}
+
+fn read_words<'a, R: BufRead + 'a>(reader: R,
+                                   tokenizer: &'a Tokenizer)
+                                   -> Box<Iterator<Item = String> + 'a> {
+    Box::new(lossy_lines(reader).flat_map(move |line| tokenizer.tokenize(&line).into_iter()))
+}
+
+fn open(path: &Path) -> Option<BufReader<File>> {
+    match File::open(path) {
+        Ok(file) => Some(BufReader::new(file)),
+        Err(e) => {
+            println!("Skipping {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Runs `work` over every file in the corpus, along with its position in the list, on
+/// `threads` threads, each with its own `state`, and hands the results to `merge` in the
+/// order the files are listed. Merging in a fixed
+/// order means the outcome is the same whatever the number of threads.
+///
+/// A file is only started once it is within `threads` files of the next to be merged, so
+/// one slow file holds the others up rather than letting their results pile up in memory.
+fn in_file_order<S, T, N, W, M>(corpus: &[PathBuf],
+                                threads: usize,
+                                state: N,
+                                work: W,
+                                mut merge: M)
+    where T: Send,
+          N: Fn() -> S + Sync,
+          W: Fn(&mut S, usize, BufReader<File>) -> T + Sync,
+          M: FnMut(T)
+{
+    if threads <= 1 {
+        let mut state = state();
+        for (i, path) in corpus.iter().enumerate() {
+            if let Some(file) = open(path) {
+                merge(work(&mut state, i, file));
+            }
+        }
+        return;
+    }
+
+    let next_file = AtomicUsize::new(0);
+    let merged = Merged {
+        count: Mutex::new(0),
+        changed: Condvar::new(),
+    };
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let (next_file, merged, state, work) = (&next_file, &merged, &state, &work);
+            scope.spawn(move || {
+                let mut state = state();
+                loop {
+                    let i = next_file.fetch_add(1, Ordering::SeqCst);
+                    if i >= corpus.len() {
+                        break;
+                    }
+                    merged.wait_for((i + 1).saturating_sub(threads));
+                    let result = open(&corpus[i]).map(|file| work(&mut state, i, file));
+                    if sender.send((i, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        // results arrive in whatever order the threads finish them, so hold on to any
+        // that are early until every file before them has been merged
+        let mut waiting = BTreeMap::new();
+        let mut next_merge = 0;
+        let _release = Release(&merged);
+        for (i, result) in receiver {
+            waiting.insert(i, result);
+            while let Some(result) = waiting.remove(&next_merge) {
+                if let Some(result) = result {
+                    merge(result);
+                }
+                next_merge += 1;
+                merged.set(next_merge);
+            }
+        }
+    });
+}
+
+/// How many files `in_file_order` has merged, for the threads reading ahead to wait on.
+struct Merged {
+    count: Mutex<usize>,
+    changed: Condvar,
+}
+
+impl Merged {
+    fn set(&self, count: usize) {
+        *self.count.lock().unwrap() = count;
+        self.changed.notify_all();
+    }
+
+    fn wait_for(&self, count: usize) {
+        let mut merged = self.count.lock().unwrap();
+        while *merged < count {
+            merged = self.changed.wait(merged).unwrap();
         }
     }
+}
+
+/// Lets every waiting thread go once merging stops, even if it stops by panicking, so that
+/// the panic isn't turned into threads waiting forever.
+struct Release<'a>(&'a Merged);
+
+impl<'a> Drop for Release<'a> {
+    fn drop(&mut self) {
+        self.0.set(usize::max_value());
+    }
+}
+
+/// Feeds every sentence of the corpus into `builder`, marking the paragraph and document
+/// boundaries between them. Each document is counted separately, on one of `threads`
+/// threads, and added to `builder` in corpus order.
+pub fn create_model(corpus: &[PathBuf],
+                    mut builder: LanguageModelBuilder,
+                    tokenizer: &Tokenizer,
+                    segmenter: &Segmenter,
+                    threads: usize)
+                    -> LanguageModelBuilder {
+    // each document's counts spill at the whole of any memory budget rather than a share of
+    // it, so they spill at the same points however many threads there are, and documents are
+    // numbered on from any the builder has already counted
+    let template = builder.empty_copy();
+    let first = builder.documents();
+    in_file_order(corpus,
+                  threads,
+                  || template.empty_copy(),
+                  |document, i, file| {
+                      document.start_document(first + i as u64);
+                      for segment in sentences(file, segmenter) {
+                          match segment {
+                              Segment::Sentence(sentence) => {
+                                  let mut acc = document.new_sentence();
+                                  for word in tokenizer.tokenize(&sentence) {
+                                      acc.add_word(&word);
+                                  }
+                              }
+                              Segment::ParagraphBreak => document.end_paragraph(),
+                          }
+                      }
+                      document.end_document();
+                      document.take_counts()
+                  },
+                  |counts| builder.add_counts(&counts));
 
     builder
 }
+
+/// Trains word2vec on the corpus itself rather than on co-occurrence counts. Every epoch
+/// streams the corpus's sentences afresh, on `config.threads` threads that all update the
+/// same vectors, and skips any word not in `vocabulary`, whose counts also give the
+/// distribution negative samples are drawn from.
+///
+/// With `config.subwords`, vectors for the words' character n-grams are learnt as well, and
+/// the model can make up vectors for words outside the vocabulary from them.
+///
+/// The learning rate falls linearly from `config.learning_rate()` towards zero over the whole
+/// of training. On one thread, the same corpus, config and `seed` always give the same model.
+pub fn train_word2vec(corpus: &[PathBuf],
+                      vocabulary: &[(String, u64)],
+                      architecture: Architecture,
+                      window: usize,
+                      tokenizer: &Tokenizer,
+                      segmenter: &Segmenter,
+                      config: &TrainingConfig,
+                      seed: u64,
+                      progress: &mut FnMut(&Progress))
+                      -> LanguageModel {
+    let words: Vec<String> = vocabulary.iter().map(|w| w.0.clone()).collect();
+    let counts: Vec<u64> = vocabulary.iter().map(|w| w.1).collect();
+    let index: HashMap<&str, usize> = words.iter().enumerate().map(|(i, w)| (&w[..], i)).collect();
+    let mut model = Word2Vec::new(architecture, &counts, window, config, seed);
+    if config.subwords {
+        model = model.learn_subwords(&words, config);
+    }
+
+    let total = counts.iter().fold(0, |x, y| x + y) as f64 * config.epochs() as f64;
+    let seen = AtomicUsize::new(0);
+    let mut monitor = Monitor::new(config, progress);
+    for epoch in 0..config.epochs() {
+        let (mut loss, mut predictions) = (0.0, 0);
+        let model = &model;
+        in_file_order(corpus,
+                      config.threads,
+                      || (),
+                      |_, i, file| {
+                          let mut rng = model.rng(epoch, i);
+                          let (mut loss, mut predictions) = (0.0, 0);
+                          for segment in sentences(file, segmenter) {
+                              if let Segment::Sentence(sentence) = segment {
+                                  let mut sentence: Vec<usize> =
+                                      tokenizer.tokenize(&sentence)
+                                               .iter()
+                                               .filter_map(|w| index.get(&w[..]).cloned())
+                                               .collect();
+                                  let done = seen.fetch_add(sentence.len(), Ordering::Relaxed);
+                                  let left = (1.0 - done as f64 / total).max(0.0001);
+                                  let rate = config.learning_rate() * left as f32;
+
+                                  model.subsample(&mut sentence, &mut rng);
+                                  let (l, p) = model.train_sentence(&sentence, rate, &mut rng);
+                                  loss += l;
+                                  predictions += p;
+                              }
+                          }
+                          (loss, predictions)
+                      },
+                      |(l, p)| {
+                          loss += l;
+                          predictions += p;
+                      });
+
+        if !monitor.epoch_done(epoch, (loss / predictions.max(1) as f64) as f32) {
+            break;
+        }
+    }
+
+    // with subwords, a word's vector takes in its n-grams as it did during training
+    let language_model = LanguageModel::from_vectors(&words,
+                                                     &model.input_vectors(),
+                                                     &model.output_vectors(),
+                                                     config);
+    match model.subwords() {
+        Some(subwords) => language_model.with_subwords(subwords),
+        None => language_model,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Tokenizer, AsciiTokenizer, UnicodeTokenizer, Segmenter, Segment, sentences};
+    use super::{find_most_common_words, create_model, train_word2vec};
+    use super::Segmentation::*;
+    use models::{LanguageModelBuilder, TrainingConfig, Architecture};
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_ascii_tokenizer() {
+        assert_eq!(AsciiTokenizer.tokenize("The café, it's 3 o'clock."),
+                   vec!["the", "caf", "it", "s", "o", "clock"]);
+    }
+
+    #[test]
+    fn test_unicode_tokenizer() {
+        assert_eq!(UnicodeTokenizer.tokenize("Ça va? L'homme a dit «naïve» 'quoted'."),
+                   vec!["ça", "va", "l'homme", "a", "dit", "naïve", "quoted"]);
+        assert_eq!(UnicodeTokenizer.tokenize("cafe\u{301} Straße"),
+                   vec!["cafe\u{301}", "straße"]);
+    }
+
+    fn split(text: &str, segmenter: &Segmenter) -> Vec<String> {
+        sentences(text.as_bytes(), segmenter)
+            .filter_map(|segment| {
+                match segment {
+                    Segment::Sentence(s) => Some(s),
+                    Segment::ParagraphBreak => None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sentences() {
+        let segmenter = Segmenter::new(Sentences);
+        let text = "Pi is about 3.14, e.g. in Mr. J. Smith's\nbook. Really?! \"Yes.\" And \
+                    so on... the end\n\nNew paragraph";
+        assert_eq!(split(text, &segmenter),
+                   vec!["Pi is about 3.14, e.g. in Mr. J. Smith's\nbook.",
+                        "Really?!",
+                        "\"Yes.\"",
+                        "And so on... the end",
+                        "New paragraph"]);
+    }
+
+    #[test]
+    fn test_paragraphs() {
+        let segmenter = Segmenter::new(Paragraphs);
+        let text = "One. Two.\nThree.\n  \nFour.\n\n\nFive";
+        assert_eq!(split(text, &segmenter),
+                   vec!["One. Two.\nThree.", "Four.", "Five"]);
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let segmenter = Segmenter::new(Sentences);
+        let text: &[u8] = b"Caf\xe9 bad. Still here.";
+        assert_eq!(sentences(text, &segmenter).collect::<Vec<_>>(),
+                   vec![Segment::Sentence("Caf\u{FFFD} bad.".to_string()),
+                        Segment::Sentence("Still here.".to_string()),
+                        Segment::ParagraphBreak]);
+    }
+
+    #[test]
+    fn test_paragraph_breaks() {
+        let segmenter = Segmenter::new(Sentences);
+        let text: &[u8] = b"\nOne. Two\n\n\nThree.\n";
+        assert_eq!(sentences(text, &segmenter).collect::<Vec<_>>(),
+                   vec![Segment::Sentence("One.".to_string()),
+                        Segment::Sentence("Two".to_string()),
+                        Segment::ParagraphBreak,
+                        Segment::Sentence("Three.".to_string()),
+                        Segment::ParagraphBreak]);
+    }
+
+    #[test]
+    fn test_threads_give_identical_counts() {
+        let dir = env::temp_dir().join("wordvec-threads-test");
+        fs::create_dir_all(&dir).unwrap();
+        let texts = ["The cat sat on the mat. The dog did not.",
+                     "A dog and a cat.\n\nThe mat was red, the cat was not.",
+                     "",
+                     "Mats and cats and dogs; the end.",
+                     "The the the cat cat dog."];
+        let mut corpus = vec![];
+        for (i, text) in texts.iter().enumerate() {
+            let path = dir.join(format!("{}.txt", i));
+            File::create(&path).unwrap().write_all(text.as_bytes()).unwrap();
+            corpus.push(path);
+        }
+
+        let segmenter = Segmenter::new(Sentences);
+        let run = |threads, budget: Option<usize>| {
+            let words = find_most_common_words(&corpus, 6, &AsciiTokenizer, threads);
+            let mut builder = LanguageModelBuilder::new(3,
+                                                        words.into_iter().map(|w| w.0).collect());
+            if let Some(bytes) = budget {
+                builder = builder.memory_budget(bytes, &dir);
+            }
+            let builder = create_model(&corpus, builder, &AsciiTokenizer, &segmenter, threads);
+
+            let path = dir.join(format!("model-{}", threads));
+            builder.save(&path).unwrap();
+            let mut bytes = vec![];
+            File::open(&path).unwrap().read_to_end(&mut bytes).unwrap();
+            bytes
+        };
+
+        let saved: Vec<_> = [1, 2, 3, 8].iter().map(|&threads| run(threads, None)).collect();
+        assert!(saved.iter().all(|bytes| *bytes == saved[0]));
+
+        // with a budget small enough that every thread has to spill
+        let spilled: Vec<_> = [1, 3].iter().map(|&threads| run(threads, Some(64))).collect();
+        assert_eq!(spilled[0], spilled[1]);
+    }
+
+    #[test]
+    fn test_long_document_spills_alike_on_any_threads() {
+        let dir = env::temp_dir().join("wordvec-long-document-test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("long.txt");
+        File::create(&path)
+            .unwrap()
+            .write_all("The cat sat on the mat and the dog sat on the rug. ".repeat(50).as_bytes())
+            .unwrap();
+        let corpus = vec![path];
+
+        let segmenter = Segmenter::new(Sentences);
+        let run = |threads| {
+            let words = find_most_common_words(&corpus, 6, &AsciiTokenizer, 1);
+            // room for all 36 cells at once, but not for an eighth of them
+            let builder = LanguageModelBuilder::new(3, words.into_iter().map(|w| w.0).collect())
+                              .memory_budget(640, &dir);
+            let builder = create_model(&corpus, builder, &AsciiTokenizer, &segmenter, threads);
+
+            let path = dir.join(format!("model-{}", threads));
+            builder.save(&path).unwrap();
+            fs::read(&path).unwrap()
+        };
+        assert_eq!(run(1), run(8));
+    }
+
+    #[test]
+    fn test_word2vec() {
+        let dir = env::temp_dir().join("wordvec-word2vec-test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("corpus.txt");
+        File::create(&path)
+            .unwrap()
+            .write_all("The cat sat on the mat. The dog sat on the rug. ".repeat(20).as_bytes())
+            .unwrap();
+        let corpus = vec![path];
+
+        let words = find_most_common_words(&corpus, 7, &AsciiTokenizer, 1);
+        let segmenter = Segmenter::new(Sentences);
+        let config = TrainingConfig {
+            rank: 10,
+            epochs: Some(3),
+            // with no minimum improvement, a rise in the loss doesn't end training early
+            min_improvement: 0.0,
+            subsample: 0.0,
+            ..TrainingConfig::default()
+        };
+        for &architecture in &[Architecture::SkipGram, Architecture::Cbow] {
+            let mut losses = vec![];
+            let model = train_word2vec(&corpus,
+                                       &words,
+                                       architecture,
+                                       2,
+                                       &AsciiTokenizer,
+                                       &segmenter,
+                                       &config,
+                                       1,
+                                       &mut |p| losses.push(p.loss));
+            assert_eq!(losses.len(), 3);
+            assert!(losses[2] < losses[0]);
+            assert!(model.get("cat").is_some() && model.get("rug").is_some());
+
+            let again = train_word2vec(&corpus,
+                                       &words,
+                                       architecture,
+                                       2,
+                                       &AsciiTokenizer,
+                                       &segmenter,
+                                       &config,
+                                       1,
+                                       &mut |_| {});
+            assert_eq!(model, again);
+        }
+
+        assert!(train_word2vec(&corpus,
+                               &words,
+                               Architecture::SkipGram,
+                               2,
+                               &AsciiTokenizer,
+                               &segmenter,
+                               &config,
+                               1,
+                               &mut |_| {})
+                    .get("cats")
+                    .is_none());
+        let config = TrainingConfig { subwords: true, buckets: 1000, ..config };
+        let model = train_word2vec(&corpus,
+                                   &words,
+                                   Architecture::SkipGram,
+                                   2,
+                                   &AsciiTokenizer,
+                                   &segmenter,
+                                   &config,
+                                   1,
+                                   &mut |_| {});
+        let (cat, cats) = (model.get("cat").unwrap(), model.get("cats").unwrap());
+        assert_eq!(cats.word, "cats");
+        assert!(cat.distance(&cats) < cat.distance(&model.get("rug").unwrap()));
+    }
+}